@@ -0,0 +1,143 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+
+use crate::feerate::FeeRate;
+use crate::limits::Limits;
+use crate::mempool::Mempool;
+
+// Bitcoin Core's selection: the transaction whose not yet selected ancestors together pay
+// the best feerate goes in along with them, then its descendants are scored again. a
+// package that doesn't fit is passed over until one of its ancestors gets in. like Core, the
+// minimum feerate applies to the package, so a child can still pay for a parent below it
+pub(crate) fn ancestor_select(mempool: &Mempool, limits: &Limits) -> Vec<usize> {
+    let n = mempool.len();
+    let placeable = mempool.eligible(FeeRate::new(0, 1));
+
+    // position in a parents first order, packages go into the block sorted by it
+    let mut position = vec![0; n];
+    let mut incomings: Vec<usize> = (0..n).map(|i| mempool.parents[i].len()).collect();
+    let mut stack: Vec<usize> = (0..n).filter(|&i| incomings[i] == 0).collect();
+    let mut next = 0;
+    while let Some(i) = stack.pop() {
+        position[i] = next;
+        next += 1;
+        for &child in &mempool.children[i] {
+            incomings[child] -= 1;
+            if incomings[child] == 0 {
+                stack.push(child);
+            }
+        }
+    }
+
+    let mut in_block = vec![false; n];
+    // a heap entry is stale once its transaction has been scored again
+    let mut version = vec![0u32; n];
+    let package = |i: usize, in_block: &[bool]| {
+        let mut package = vec![i];
+        let mut seen = HashSet::from([i]);
+        let mut k = 0;
+        while k < package.len() {
+            for &p in &mempool.parents[package[k]] {
+                if !in_block[p] && seen.insert(p) {
+                    package.push(p);
+                }
+            }
+            k += 1;
+        }
+        package
+    };
+    let score = |package: &[usize]| FeeRate::new(mempool.modified_fee(package), mempool.weight(package));
+
+    let mut heap: BinaryHeap<(FeeRate, Reverse<&str>, usize, u32)> = (0..n)
+        .filter(|&i| placeable[i])
+        .map(|i| (score(&package(i, &in_block)), Reverse(mempool.nodes[i].txid.as_str()), i, 0))
+        .collect();
+
+    let mut block: Vec<usize> = Vec::new();
+    let mut block_weight = 0;
+    let mut block_sigops = 0;
+    while let Some((feerate, _, i, v)) = heap.pop() {
+        if in_block[i] || version[i] != v {
+            continue;
+        }
+        // every package left pays less
+        if feerate < limits.min_feerate {
+            break;
+        }
+        let mut added = package(i, &in_block);
+        let weight = mempool.weight(&added);
+        let sigops = mempool.sigops(&added);
+        if block_weight + weight > limits.weight() || block_sigops + sigops > limits.sigops() {
+            continue;
+        }
+        block_weight += weight;
+        block_sigops += sigops;
+        added.sort_by_key(|&j| position[j]);
+        for &j in &added {
+            in_block[j] = true;
+        }
+
+        // everything below the package has fewer ancestors left to pay for
+        let mut below: HashSet<usize> = HashSet::new();
+        let mut stack: Vec<usize> = added.clone();
+        while let Some(j) = stack.pop() {
+            for &child in &mempool.children[j] {
+                if !in_block[child] && below.insert(child) {
+                    stack.push(child);
+                }
+            }
+        }
+        for j in below {
+            version[j] += 1;
+            heap.push((score(&package(j, &in_block)), Reverse(mempool.nodes[j].txid.as_str()), j, version[j]));
+        }
+        block.extend(added);
+    }
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{FeeDistribution, Synthetic};
+    use crate::validate::Validation;
+
+    fn limits(max_weight: u64) -> Limits {
+        let mut limits = Limits::new(max_weight);
+        limits.reserved_weight = 0;
+        limits
+    }
+
+    #[test]
+    fn test_child_pays_for_parent() {
+        let txid = |c: char| c.to_string().repeat(64);
+        // b pays for a, which alone pays less than c
+        let csv = format!("{},100,400,\n{},2000,400,{}\n{},900,400,\n", txid('a'), txid('b'), txid('a'), txid('c'));
+        let mempool = Mempool::from_csv(csv.as_bytes(), Validation::Strict).unwrap();
+
+        let greedy = mempool.pack(&mempool.eligible(FeeRate::new(0, 1)), &limits(800));
+        assert_eq!(greedy, vec![2, 0]);
+        let block = ancestor_select(&mempool, &limits(800));
+        assert_eq!(block, vec![0, 1]);
+        assert!(mempool.modified_fee(&block) > mempool.modified_fee(&greedy));
+
+        // the package as a whole has to clear the minimum feerate, a doesn't need to alone
+        let mut limits = limits(800);
+        limits.min_feerate = FeeRate::new(5, 2);
+        assert_eq!(ancestor_select(&mempool, &limits), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_parents_come_first() {
+        let synthetic = Synthetic { size: 2000, max_depth: 25, fees: FeeDistribution::Exponential { mean: 10.0 }, seed: 3 };
+        let mempool = Mempool::from_csv(synthetic.to_csv().as_bytes(), Validation::Strict).unwrap();
+        let block = ancestor_select(&mempool, &limits(400_000));
+        assert!(!block.is_empty() && block.len() < mempool.len());
+
+        let mut placed = vec![false; mempool.len()];
+        for &i in &block {
+            assert!(mempool.parents[i].iter().all(|&p| placed[p]), "{} before its parents", mempool.nodes[i].txid);
+            placed[i] = true;
+        }
+    }
+}
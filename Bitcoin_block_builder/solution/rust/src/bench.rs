@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use rust::{verify, BlockBuilder, Mempool, Strategy};
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+// the system allocator, counting what is live and the most that was, so a run can report
+// how much heap it took on top of what was there before. only with the count-allocations
+// feature, every other command would pay for the counting too
+#[cfg(feature = "count-allocations")]
+mod counting {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::Ordering;
+
+    use super::{LIVE, PEAK};
+
+    struct Counting;
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+                PEAK.fetch_max(live, Ordering::Relaxed);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: Counting = Counting;
+}
+
+struct Run {
+    name: String,
+    fee: u64,
+    weight: u64,
+    count: usize,
+    elapsed: Duration,
+    // most heap held during the run beyond what was live when it started, when counted
+    peak_bytes: Option<usize>,
+    valid: bool,
+}
+
+// every strategy under the limits of `base`, alone and with the branch and bound search for
+// `budget` on top
+pub(crate) fn run(mempool: &Mempool, base: &BlockBuilder, budget: Duration) {
+    let strategies = [("greedy", Strategy::Greedy), ("ancestor-feerate", Strategy::AncestorFeerate)];
+    let mut variants: Vec<(String, BlockBuilder)> = Vec::new();
+    for (name, strategy) in strategies {
+        variants.push((name.to_string(), base.clone().strategy(strategy)));
+    }
+    for (name, strategy) in strategies {
+        let builder = base.clone().strategy(strategy).optimize(budget);
+        variants.push((format!("{}+bnb {}s", name, budget.as_secs_f64()), builder));
+    }
+
+    let mut runs: Vec<Run> = Vec::new();
+    for (name, builder) in variants {
+        let start_bytes = LIVE.load(Ordering::Relaxed);
+        PEAK.store(start_bytes, Ordering::Relaxed);
+        let start = Instant::now();
+        let block = builder.build(mempool);
+        let elapsed = start.elapsed();
+        let peak_bytes = cfg!(feature = "count-allocations").then(|| PEAK.load(Ordering::Relaxed) - start_bytes);
+
+        runs.push(Run {
+            name,
+            fee: block.fee,
+            weight: block.weight,
+            count: block.txids.len(),
+            elapsed,
+            peak_bytes,
+            valid: verify(mempool, &block.txids, base.limits()).is_valid(),
+        });
+    }
+
+    println!(
+        "{:<28} {:>12} {:>10} {:>8} {:>10} {:>10} {:>6}",
+        "Strategy", "Fee", "Weight", "Txs", "Time ms", "Peak KiB", "Valid"
+    );
+    for run in &runs {
+        println!(
+            "{:<28} {:>12} {:>10} {:>8} {:>10.1} {:>10} {:>6}",
+            run.name,
+            run.fee,
+            run.weight,
+            run.count,
+            run.elapsed.as_secs_f64() * 1000.0,
+            run.peak_bytes.map_or("-".to_string(), |bytes| (bytes / 1024).to_string()),
+            if run.valid { "yes" } else { "NO" }
+        );
+    }
+}
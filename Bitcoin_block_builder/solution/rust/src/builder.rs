@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::time::Duration;
+
+use crate::ancestor::ancestor_select;
+use crate::feerate::FeeRate;
+use crate::limits::Limits;
+use crate::mempool::Mempool;
+use crate::optimize::{branch_and_bound, fill_gap, Budget};
+
+// the gap left after greedy is small, this is plenty to search it through. counted in
+// search nodes rather than time so the same mempool always gets the same block
+const FILL_NODES: u64 = 100_000;
+
+// how the block is first filled, before any search on top of it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    // best feerate first among the transactions whose parents are in already
+    Greedy,
+    // best feerate of a transaction together with its ancestors first
+    AncestorFeerate,
+}
+
+// picks transactions from a mempool into a block within `limits`
+#[derive(Clone, Debug)]
+pub struct BlockBuilder {
+    limits: Limits,
+    strategy: Strategy,
+    optimize: Option<Duration>,
+}
+
+// a block in the order its transactions have to be mined, every parent before its children
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub txids: Vec<String>,
+    // what the transactions pay, and what they are worth once prioritised
+    pub fee: u64,
+    pub modified_fee: u64,
+    pub weight: u64,
+    pub sigops: u64,
+    pub limits: Limits,
+    // lowest modified feerate of a transaction in the block, and the one at its middle weight
+    // unit
+    pub min_feerate: FeeRate,
+    pub median_feerate: FeeRate,
+    pub stats: BuildStats,
+}
+
+// the blocks the mempool is expected to fill one after the other, the next one first
+#[derive(Clone, Debug)]
+pub struct Projection {
+    pub blocks: Vec<Block>,
+    index: HashMap<String, usize>,
+}
+
+// how each step of the build went, in modified fees
+#[derive(Clone, Debug)]
+pub struct BuildStats {
+    // fee of the strategy's block, before the search and the gap fill
+    pub selected_fee: u64,
+    // set when the builder ran the branch and bound search
+    pub optimized: Option<SearchStats>,
+    pub gap_fill: GapFill,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchStats {
+    pub fee: u64,
+    // no block from this mempool can collect more than this
+    pub upper_bound: u64,
+    // the whole tree was searched, so `fee` is the optimum
+    pub complete: bool,
+    pub nodes: u64,
+}
+
+// what the gap fill added on top of the selection
+#[derive(Clone, Debug)]
+pub struct GapFill {
+    pub fee: u64,
+    pub weight: u64,
+    pub count: usize,
+    pub complete: bool,
+}
+
+impl BlockBuilder {
+    pub fn new(max_weight: u64) -> BlockBuilder {
+        BlockBuilder { limits: Limits::new(max_weight), strategy: Strategy::Greedy, optimize: None }
+    }
+
+    // weight kept back for the header and the coinbase
+    pub fn reserved_weight(mut self, weight: u64) -> BlockBuilder {
+        self.limits.reserved_weight = weight;
+        self
+    }
+
+    // sigops of the whole block, the coinbase's share included
+    pub fn max_sigops(mut self, sigops: u64) -> BlockBuilder {
+        self.limits.max_sigops = sigops;
+        self
+    }
+
+    pub fn min_feerate(mut self, feerate: FeeRate) -> BlockBuilder {
+        self.limits.min_feerate = feerate;
+        self
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> BlockBuilder {
+        self.strategy = strategy;
+        self
+    }
+
+    // the strategy first, then branch and bound on top of it for `budget`
+    pub fn optimize(mut self, budget: Duration) -> BlockBuilder {
+        self.optimize = Some(budget);
+        self
+    }
+
+    pub fn build(&self, mempool: &Mempool) -> Block {
+        let (block, stats) = self.select(mempool);
+        Block::new(mempool, &block, self.limits, stats)
+    }
+
+    // builds up to `count` blocks, each from what the ones before it left in the mempool
+    pub fn project(&self, mempool: &Mempool, count: usize) -> Projection {
+        let mut projection = Projection { blocks: Vec::new(), index: HashMap::new() };
+        let mut remaining = mempool.clone();
+        while projection.blocks.len() < count && !remaining.is_empty() {
+            let (block, stats) = self.select(&remaining);
+            if block.is_empty() {
+                break;
+            }
+            let block = Block::new(&remaining, &block, self.limits, stats);
+            for txid in &block.txids {
+                projection.index.insert(txid.clone(), projection.blocks.len());
+            }
+
+            let mined: Vec<bool> = (0..remaining.len())
+                .map(|i| projection.index.contains_key(&remaining.nodes[i].txid))
+                .collect();
+            remaining = remaining.remaining(&mined);
+            projection.blocks.push(block);
+        }
+        projection
+    }
+
+    fn select(&self, mempool: &Mempool) -> (Vec<usize>, BuildStats) {
+        let selected = match self.strategy {
+            Strategy::Greedy => mempool.pack(&mempool.eligible(self.limits.min_feerate), &self.limits),
+            Strategy::AncestorFeerate => ancestor_select(mempool, &self.limits),
+        };
+        let selected_fee = mempool.modified_fee(&selected);
+
+        let (block, optimized) = match self.optimize {
+            Some(budget) => {
+                let optimized = branch_and_bound(mempool, &self.limits, &selected, budget);
+                let stats = SearchStats {
+                    fee: optimized.fee,
+                    upper_bound: optimized.upper_bound,
+                    complete: optimized.complete,
+                    nodes: optimized.nodes,
+                };
+                (optimized.block, Some(stats))
+            }
+            None => (selected, None),
+        };
+
+        // greedy and a search that ran to the end leave nothing that fits, the gap fill is
+        // only worth it after a selection that stopped early
+        if !mempool.can_extend(&block, &mempool.eligible(self.limits.min_feerate), &self.limits) {
+            let gap_fill = GapFill { fee: 0, weight: 0, count: 0, complete: true };
+            return (block, BuildStats { selected_fee, optimized, gap_fill });
+        }
+        let filled = fill_gap(mempool, &self.limits, &block, Budget::Nodes(FILL_NODES));
+        let gap_fill = GapFill {
+            fee: filled.fee.saturating_sub(mempool.modified_fee(&block)),
+            weight: mempool.weight(&filled.block) - mempool.weight(&block),
+            count: filled.block.len() - block.len(),
+            complete: filled.complete,
+        };
+        (filled.block, BuildStats { selected_fee, optimized, gap_fill })
+    }
+}
+
+impl Block {
+    fn new(mempool: &Mempool, block: &[usize], limits: Limits, stats: BuildStats) -> Block {
+        let mut feerates: Vec<(FeeRate, u64)> = block
+            .iter()
+            .map(|&i| (mempool.nodes[i].feerate(), mempool.nodes[i].weight))
+            .collect();
+        feerates.sort();
+        let weight = mempool.weight(block);
+        let mut below = 0;
+        let median_feerate = feerates
+            .iter()
+            .find(|(_, w)| {
+                below += w;
+                below * 2 >= weight
+            })
+            .map_or(FeeRate::new(0, 0), |(feerate, _)| *feerate);
+
+        Block {
+            txids: block.iter().map(|&i| mempool.nodes[i].txid.clone()).collect(),
+            fee: mempool.fee(block),
+            modified_fee: mempool.modified_fee(block),
+            weight,
+            sigops: mempool.sigops(block),
+            limits,
+            min_feerate: feerates.first().map_or(FeeRate::new(0, 0), |(feerate, _)| *feerate),
+            median_feerate,
+            stats,
+        }
+    }
+
+    // share of the weight left to transactions that they fill
+    pub fn packing_efficiency(&self) -> f64 {
+        self.weight as f64 / self.limits.weight() as f64
+    }
+
+    // one txid per line, the format block.txt is graded in
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), Box<dyn Error>> {
+        writer.write_all(self.txids.join("\n").as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Projection {
+    // 0 for the next block, None past the projected ones
+    pub fn block_index(&self, txid: &str) -> Option<usize> {
+        self.index.get(txid).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::MAX_BLOCK_WEIGHT;
+    use crate::synthetic::{FeeDistribution, Synthetic};
+    use crate::validate::Validation;
+
+    #[test]
+    fn test_limits_are_honored() {
+        let txid = |c: char| c.to_string().repeat(64);
+        // a pays 10 sat/WU and takes 20 sigops, b 5 sat/WU, c 1 sat/WU
+        let csv = format!("{},4000,400,,20\n{},2000,400,\n{},400,400,\n", txid('a'), txid('b'), txid('c'));
+        let mempool = Mempool::from_csv(csv.as_bytes(), Validation::Strict).unwrap();
+        let build = |builder: BlockBuilder| builder.build(&mempool).txids;
+
+        assert_eq!(build(BlockBuilder::new(1200).reserved_weight(0)).len(), 3);
+        // the default reservation leaves no room at all in a block this small
+        assert!(build(BlockBuilder::new(1200)).is_empty());
+        assert_eq!(build(BlockBuilder::new(1200).reserved_weight(400)), vec![txid('a'), txid('b')]);
+        assert_eq!(build(BlockBuilder::new(1200).reserved_weight(0).max_sigops(410)), vec![txid('b'), txid('c')]);
+        assert_eq!(
+            build(BlockBuilder::new(1200).reserved_weight(0).min_feerate(FeeRate::new(2, 1))),
+            vec![txid('a'), txid('b')]
+        );
+        let ancestor = BlockBuilder::new(1200).reserved_weight(0).strategy(Strategy::AncestorFeerate);
+        assert_eq!(build(ancestor.min_feerate(FeeRate::new(2, 1))), vec![txid('a'), txid('b')]);
+    }
+
+    #[test]
+    fn test_prioritise_orders_by_modified_fee() {
+        let txid = |c: char| c.to_string().repeat(64);
+        let csv = format!("{},4000,400,\n{},400,400,\n", txid('a'), txid('b'));
+        let mut mempool = Mempool::from_csv(csv.as_bytes(), Validation::Strict).unwrap();
+        assert!(mempool.prioritise(&txid('b'), 5000));
+        assert!(mempool.prioritise(&txid('a'), -10000));
+        assert!(!mempool.prioritise(&txid('c'), 1));
+
+        let block = BlockBuilder::new(800).reserved_weight(400).build(&mempool);
+        assert_eq!(block.txids, vec![txid('b')]);
+        assert_eq!((block.fee, block.modified_fee), (400, 5400));
+    }
+
+    #[test]
+    fn test_prioritise_near_u64_max() {
+        let txid = |c: char| c.to_string().repeat(64);
+        // b spends a, both prioritised to just short of u64::MAX, so any two of them sum past it
+        let csv = format!("{},1000,400,\n{},1000,400,{}\n{},1000,400,\n", txid('a'), txid('b'), txid('a'), txid('c'));
+        let mut mempool = Mempool::from_csv(csv.as_bytes(), Validation::Strict).unwrap();
+        for c in ['a', 'b'] {
+            assert!(mempool.prioritise(&txid(c), i64::MAX));
+            assert!(mempool.prioritise(&txid(c), i64::MAX - 1000));
+        }
+        assert_eq!(mempool.nodes[0].modified_fee, u64::MAX - 1);
+
+        let builder = BlockBuilder::new(1200).reserved_weight(0);
+        for builder in [
+            builder.clone(),
+            builder.clone().strategy(Strategy::AncestorFeerate),
+            builder.clone().optimize(Duration::from_secs(60)),
+        ] {
+            let block = builder.build(&mempool);
+            assert_eq!(block.txids, vec![txid('a'), txid('b'), txid('c')]);
+            assert_eq!((block.fee, block.modified_fee), (3000, u64::MAX));
+        }
+
+        // the gap after c alone holds a and b, worth more than a u64 between them
+        let filled = fill_gap(&mempool, builder.limits(), &[2], Budget::Nodes(1000));
+        assert!(filled.complete);
+        assert_eq!((filled.fee, filled.upper_bound), (u64::MAX, u64::MAX));
+    }
+
+    #[test]
+    fn test_builder_api() {
+        let txid = |c: char| c.to_string().repeat(64);
+        // greedy takes c and d, a and b together are worth more than d
+        let csv = format!("{},10,400,\n{},1000,200,{}\n{},700,400,\n{},390,400,\n", txid('a'), txid('b'), txid('a'), txid('c'), txid('d'));
+        let mempool = Mempool::from_csv(csv.as_bytes(), Validation::Strict).unwrap();
+        let builder = BlockBuilder::new(1000).reserved_weight(0);
+        assert_eq!(builder.limits().weight(), 1000);
+
+        let block = builder.build(&mempool);
+        assert_eq!(block.txids, vec![txid('c'), txid('d')]);
+        assert_eq!((block.fee, block.modified_fee, block.weight, block.sigops), (1090, 1090, 800, 0));
+        assert_eq!(block.stats.selected_fee, 1090);
+        assert!(block.stats.optimized.is_none());
+        assert_eq!(block.stats.gap_fill.count, 0);
+        assert!(block.stats.gap_fill.complete);
+        assert_eq!(block.packing_efficiency(), 0.8);
+
+        let block = builder.clone().optimize(Duration::from_secs(60)).build(&mempool);
+        assert_eq!(block.txids, vec![txid('c'), txid('a'), txid('b')]);
+        assert_eq!(block.fee, 1710);
+        assert_eq!(block.stats.selected_fee, 1090);
+        let optimized = block.stats.optimized.as_ref().unwrap();
+        assert!(optimized.complete);
+        assert_eq!((optimized.fee, optimized.upper_bound), (1710, 1710));
+
+        let mut written = Vec::new();
+        block.write_to(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), block.txids.join("\n"));
+    }
+
+    #[test]
+    fn test_project_confirms_children_after_parents() {
+        let txid = |c: char| c.to_string().repeat(64);
+        // one transaction per block: b pays the most but has to wait for a
+        let csv = format!("{},100,400,\n{},1000,400,{}\n{},50,400,\n", txid('a'), txid('b'), txid('a'), txid('c'));
+        let mempool = Mempool::from_csv(csv.as_bytes(), Validation::Strict).unwrap();
+
+        // the mempool runs dry after three blocks of the five asked for
+        let projection = BlockBuilder::new(400).reserved_weight(0).project(&mempool, 5);
+        let blocks: Vec<Vec<String>> = projection.blocks.iter().map(|block| block.txids.clone()).collect();
+        assert_eq!(blocks, vec![vec![txid('a')], vec![txid('b')], vec![txid('c')]]);
+        assert_eq!(projection.block_index(&txid('a')), Some(0));
+        assert_eq!(projection.block_index(&txid('b')), Some(1));
+        assert_eq!(projection.block_index(&txid('d')), None);
+
+        let projection = BlockBuilder::new(400).reserved_weight(0).project(&mempool, 2);
+        assert_eq!(projection.blocks.len(), 2);
+        assert_eq!(projection.block_index(&txid('c')), None);
+    }
+
+    #[test]
+    fn test_block_feerates() {
+        let txid = |c: char| c.to_string().repeat(64);
+        // 1, 2 and 3 sat/WU, the last one holding most of the weight
+        let csv = format!("{},100,100,\n{},400,200,\n{},3000,1000,\n", txid('a'), txid('b'), txid('c'));
+        let mempool = Mempool::from_csv(csv.as_bytes(), Validation::Strict).unwrap();
+
+        let projection = BlockBuilder::new(1300).reserved_weight(0).project(&mempool, 3);
+        assert_eq!(projection.blocks.len(), 1);
+        let block = &projection.blocks[0];
+        assert_eq!(block.min_feerate, FeeRate::new(1, 1));
+        // the median goes by weight, not by count
+        assert_eq!(block.median_feerate, FeeRate::new(3, 1));
+
+        // c fills the first block on its own, a and b share the next
+        let projection = BlockBuilder::new(1000).reserved_weight(0).project(&mempool, 3);
+        let feerates: Vec<(FeeRate, FeeRate)> =
+            projection.blocks.iter().map(|block| (block.min_feerate, block.median_feerate)).collect();
+        assert_eq!(
+            feerates,
+            vec![
+                (FeeRate::new(3, 1), FeeRate::new(3, 1)),
+                (FeeRate::new(1, 1), FeeRate::new(2, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_large_mempool() {
+        let synthetic = Synthetic { size: 200_000, max_depth: 25, fees: FeeDistribution::Exponential { mean: 10.0 }, seed: 2 };
+        let mempool = Mempool::from_csv(synthetic.to_csv().as_bytes(), Validation::Strict).unwrap();
+        let builder = BlockBuilder::new(MAX_BLOCK_WEIGHT);
+        let block = builder.build(&mempool);
+        assert!(block.weight <= builder.limits().weight() && !block.txids.is_empty());
+        assert_eq!(block.stats.gap_fill.count, 0);
+        assert!(crate::verify::verify(&mempool, &block.txids, builder.limits()).is_valid());
+    }
+}
@@ -0,0 +1,123 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+// fee per weight unit kept as the exact fraction and compared by cross multiplication, the
+// same type as the code challenge solution's
+#[derive(Clone, Copy, Debug)]
+pub struct FeeRate {
+    pub fee: u64,
+    pub weight: u64,
+}
+
+impl FeeRate {
+    pub fn new(fee: u64, weight: u64) -> Self {
+        FeeRate { fee, weight }
+    }
+
+    // rate of `sat_vb` sat per virtual byte, one vbyte being 4 weight units. fractions of a
+    // sat/vB go through `parse`
+    pub fn from_sat_per_vbyte(sat_vb: u64) -> Self {
+        FeeRate::new(sat_vb, 4)
+    }
+
+    pub fn sat_per_vbyte(&self) -> f64 {
+        let rate = self.normalized();
+        4.0 * rate.fee as f64 / rate.weight as f64
+    }
+
+    // 0/0 is treated as a zero rate, x/0 with x > 0 as an infinite one
+    fn normalized(&self) -> FeeRate {
+        if self.weight == 0 && self.fee == 0 {
+            FeeRate { fee: 0, weight: 1 }
+        } else {
+            *self
+        }
+    }
+}
+
+impl Ord for FeeRate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let a = self.normalized();
+        let b = other.normalized();
+
+        match (a.weight == 0, b.weight == 0) {
+            (true, true) => a.fee.cmp(&b.fee),
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => (a.fee as u128 * b.weight as u128).cmp(&(b.fee as u128 * a.weight as u128)),
+        }
+    }
+}
+
+impl PartialOrd for FeeRate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FeeRate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FeeRate {}
+
+// a decimal number of sat/vB such as `1.25`, kept exactly however many decimals it has
+impl FromStr for FeeRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FeeRate, String> {
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+        let digits = format!("{}{}", whole, fraction);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("expected sat/vB, found {}", s));
+        }
+        let weight = 10u64.checked_pow(fraction.len() as u32).and_then(|scale| scale.checked_mul(4));
+        match (digits.parse::<u64>(), weight) {
+            (Ok(fee), Some(weight)) => Ok(FeeRate::new(fee, weight)),
+            _ => Err(format!("{} sat/vB is out of range", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feerate_cross_multiplication() {
+        assert_eq!(FeeRate::new(1, 2), FeeRate::new(2, 4));
+        assert!(FeeRate::new(1, 3) < FeeRate::new(1, 2));
+
+        // these two collapse to the same f64
+        let a = FeeRate::new(u64::MAX, u64::MAX - 1);
+        let b = FeeRate::new(u64::MAX - 1, u64::MAX - 2);
+        assert_eq!(a.fee as f64 / a.weight as f64, b.fee as f64 / b.weight as f64);
+        assert!(a < b);
+    }
+
+    #[test]
+    fn test_feerate_zero_weight() {
+        assert!(FeeRate::new(1, 0) > FeeRate::new(u64::MAX, 1));
+        assert!(FeeRate::new(2, 0) > FeeRate::new(1, 0));
+        assert_eq!(FeeRate::new(0, 0), FeeRate::new(0, 10));
+        assert!(FeeRate::new(0, 0) < FeeRate::new(1, 10));
+        assert_eq!(FeeRate::new(0, 0).sat_per_vbyte(), 0.0);
+    }
+
+    #[test]
+    fn test_feerate_from_sat_per_vbyte() {
+        assert_eq!(FeeRate::from_sat_per_vbyte(2), FeeRate::new(1, 2));
+        assert_eq!(FeeRate::from_sat_per_vbyte(3).sat_per_vbyte(), 3.0);
+
+        assert_eq!("1.5".parse::<FeeRate>().unwrap(), FeeRate::new(3, 8));
+        assert_eq!("2".parse::<FeeRate>().unwrap(), FeeRate::from_sat_per_vbyte(2));
+        assert_eq!(".25".parse::<FeeRate>().unwrap(), FeeRate::new(1, 16));
+        // nothing is rounded off, however small the difference
+        assert!("1.0001".parse::<FeeRate>().unwrap() > "1.00009".parse::<FeeRate>().unwrap());
+        for bad in ["", ".", "-1", "1.5.0", "1e3", "abc", "99999999999999999999"] {
+            assert!(bad.parse::<FeeRate>().is_err(), "{}", bad);
+        }
+    }
+}
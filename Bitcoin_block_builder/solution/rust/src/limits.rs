@@ -0,0 +1,40 @@
+use crate::feerate::FeeRate;
+
+// consensus limits on a block
+pub const MAX_BLOCK_WEIGHT: u64 = 4000000;
+pub const MAX_BLOCK_SIGOPS: u64 = 80000;
+
+// what Bitcoin Core's block assembler keeps back for the header and the coinbase
+pub const COINBASE_RESERVED_WEIGHT: u64 = 4000;
+pub const COINBASE_RESERVED_SIGOPS: u64 = 400;
+
+// what the transactions of a block may add up to, and the least they have to pay
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub max_weight: u64,
+    pub reserved_weight: u64,
+    pub max_sigops: u64,
+    pub reserved_sigops: u64,
+    pub min_feerate: FeeRate,
+}
+
+impl Limits {
+    pub fn new(max_weight: u64) -> Limits {
+        Limits {
+            max_weight,
+            reserved_weight: COINBASE_RESERVED_WEIGHT,
+            max_sigops: MAX_BLOCK_SIGOPS,
+            reserved_sigops: COINBASE_RESERVED_SIGOPS,
+            min_feerate: FeeRate::new(0, 1),
+        }
+    }
+
+    // what is left to the transactions once the coinbase has its share
+    pub fn weight(&self) -> u64 {
+        self.max_weight.saturating_sub(self.reserved_weight)
+    }
+
+    pub fn sigops(&self) -> u64 {
+        self.max_sigops.saturating_sub(self.reserved_sigops)
+    }
+}
@@ -1,7 +1,321 @@
-mod lib;
+mod bench;
 
-fn main() {
-    lib::start();
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+
+use rust::{BlockBuilder, FeeDistribution, FeeRate, Mempool, Synthetic, Validation, COINBASE_RESERVED_WEIGHT};
+use rust::{read_fee_deltas, MAX_BLOCK_SIGOPS, MAX_BLOCK_WEIGHT};
+
+const USAGE: &str = "usage: rust [build] [--input FILE] [--output FILE] [--max-weight N] [--reserved-weight N] [--max-sigops N] [--min-feerate SAT_PER_VB] [--optimize SECONDS] [--strict] [--format csv|jsonl] [--priorities FILE]
+       rust verify [--input FILE] [--block FILE] [--max-weight N] [--reserved-weight N] [--max-sigops N] [--strict] [--format csv|jsonl]
+       rust project [--blocks N] [--input FILE] [--output FILE] [--max-weight N] [--reserved-weight N] [--max-sigops N] [--min-feerate SAT_PER_VB] [--optimize SECONDS] [--strict] [--format csv|jsonl] [--priorities FILE]
+       rust bench [--input FILE | --size N [--depth N] [--fees DIST] [--seed N]] [--max-weight N] [--reserved-weight N] [--max-sigops N] [--min-feerate SAT_PER_VB] [--optimize SECONDS] [--priorities FILE]
+       rust generate [--size N] [--depth N] [--fees uniform:MIN:MAX|exponential:MEAN] [--seed N] [--output FILE]";
+
+#[derive(Clone)]
+enum Command {
+    Build,
+    // checks a block file against the mempool instead of building one
+    Verify,
+    // the next `--blocks` blocks and which of them each transaction lands in
+    Project,
+    // every selection strategy on the same mempool, side by side
+    Bench,
+    // writes a synthetic mempool
+    Generate,
+}
+
+#[derive(Clone)]
+struct Options {
+    command: Command,
+    input: String,
+    // block.txt when building, `txid,block` lines when projecting, the mempool when generating
+    output: Option<String>,
+    block: String,
+    blocks: usize,
+    max_weight: u64,
+    reserved_weight: u64,
+    max_sigops: u64,
+    // given in sat/vB
+    min_feerate: Option<FeeRate>,
+    // `--optimize <SECONDS>` searches for a better block than greedy for that long
+    optimize: Option<f64>,
+    // rows that can't go into a block are dropped and reported unless `--strict` asks to fail
+    validation: Validation,
+    // `csv` or `jsonl`, by the input's extension when not given
+    format: Option<String>,
+    // `txid,fee_delta` rows that change the fee selection goes by
+    priorities: Option<String>,
+    // a synthetic mempool of `--size` transactions stands in for the input when given
+    size: Option<usize>,
+    depth: usize,
+    fees: FeeDistribution,
+    seed: u64,
+}
+
+// the value after `flag`, parsed
+fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, Box<dyn Error>> {
+    let value = args.next().ok_or(format!("{} needs a value\n{}", flag, USAGE))?;
+    value.parse().map_err(|_| format!("{}: bad value {}", flag, value).into())
+}
+
+// None when only the usage was asked for
+fn parse_args() -> Result<Option<Options>, Box<dyn Error>> {
+    // defaults are the paths the builder has always read and written
+    let mut options = Options {
+        command: Command::Build,
+        input: "../../../mempool.csv".to_string(),
+        output: None,
+        block: "../../block.txt".to_string(),
+        blocks: 3,
+        max_weight: MAX_BLOCK_WEIGHT,
+        reserved_weight: COINBASE_RESERVED_WEIGHT,
+        max_sigops: MAX_BLOCK_SIGOPS,
+        min_feerate: None,
+        optimize: None,
+        validation: Validation::Lenient,
+        format: None,
+        priorities: None,
+        size: None,
+        // as deep as Bitcoin Core lets ancestor chains get
+        depth: 25,
+        fees: FeeDistribution::Exponential { mean: 10.0 },
+        seed: 1,
+    };
+
+    let mut args = std::env::args().skip(1).peekable();
+    let command = match args.peek().map(|arg| arg.as_str()) {
+        Some("build") => Some(Command::Build),
+        Some("verify") => Some(Command::Verify),
+        Some("project") => Some(Command::Project),
+        Some("bench") => Some(Command::Bench),
+        Some("generate") => Some(Command::Generate),
+        _ => None,
+    };
+    if let Some(command) = command {
+        options.command = command;
+        args.next();
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => options.input = value(&mut args, "--input")?,
+            "--output" => options.output = Some(value(&mut args, "--output")?),
+            "--block" => options.block = value(&mut args, "--block")?,
+            "--blocks" => options.blocks = value(&mut args, "--blocks")?,
+            "--max-weight" => options.max_weight = value(&mut args, "--max-weight")?,
+            "--reserved-weight" => options.reserved_weight = value(&mut args, "--reserved-weight")?,
+            "--max-sigops" => options.max_sigops = value(&mut args, "--max-sigops")?,
+            "--min-feerate" => options.min_feerate = Some(value(&mut args, "--min-feerate")?),
+            "--optimize" => options.optimize = Some(value(&mut args, "--optimize")?),
+            "--strict" => options.validation = Validation::Strict,
+            "--format" => options.format = Some(value(&mut args, "--format")?),
+            "--priorities" => options.priorities = Some(value(&mut args, "--priorities")?),
+            "--size" => options.size = Some(value(&mut args, "--size")?),
+            "--depth" => options.depth = value(&mut args, "--depth")?,
+            "--fees" => options.fees = value(&mut args, "--fees")?,
+            "--seed" => options.seed = value(&mut args, "--seed")?,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return Ok(None);
+            }
+            _ => return Err(format!("unknown argument {}\n{}", arg, USAGE).into()),
+        }
+    }
+    Ok(Some(options))
+}
+
+fn synthetic(options: &Options) -> Synthetic {
+    Synthetic { size: options.size.unwrap_or(5000), max_depth: options.depth, fees: options.fees, seed: options.seed }
+}
+
+fn load(options: &Options) -> Result<Mempool, Box<dyn Error>> {
+    let mut mempool = read_mempool(options)?;
+    if let Some(path) = &options.priorities {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let deltas = read_fee_deltas(file).map_err(|e| format!("{}: {}", path, e))?;
+        let mut applied = 0;
+        for (txid, delta) in &deltas {
+            if mempool.prioritise(txid, *delta) {
+                applied += 1;
+            } else {
+                eprintln!("{}: {} isn't in the mempool, its delta is ignored", path, txid);
+            }
+        }
+        eprintln!("Prioritised {} of {} transactions in {}", applied, deltas.len(), path);
+    }
+    Ok(mempool)
+}
+
+fn read_mempool(options: &Options) -> Result<Mempool, Box<dyn Error>> {
+    if options.size.is_some() {
+        return Mempool::from_csv(synthetic(options).to_csv().as_bytes(), Validation::Strict);
+    }
+    let file = File::open(&options.input).map_err(|e| format!("{}: {}", options.input, e))?;
+    let format = options.format.as_deref().unwrap_or_else(|| {
+        if options.input.ends_with(".jsonl") || options.input.ends_with(".ndjson") { "jsonl" } else { "csv" }
+    });
+    let mempool = match format {
+        "csv" => Mempool::from_csv(file, options.validation)?,
+        "jsonl" => Mempool::from_json_lines(file, options.validation)?,
+        _ => return Err(format!("unknown format {}\n{}", format, USAGE).into()),
+    };
+    for issue in mempool.issues() {
+        eprintln!("{}", issue);
+    }
+    if !mempool.issues().is_empty() {
+        eprintln!("Dropped {} rows of {}", mempool.issues().len(), options.input);
+    }
+    Ok(mempool)
+}
+
+// a builder within the limits the options set, optimizing when asked to
+fn builder(options: &Options) -> BlockBuilder {
+    let mut builder = BlockBuilder::new(options.max_weight)
+        .reserved_weight(options.reserved_weight)
+        .max_sigops(options.max_sigops);
+    if let Some(feerate) = options.min_feerate {
+        builder = builder.min_feerate(feerate);
+    }
+    if let Some(seconds) = options.optimize {
+        builder = builder.optimize(Duration::from_secs_f64(seconds));
+    }
+    builder
 }
 
+fn build(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mempool = load(options)?;
+    let block = builder(options).build(&mempool);
 
+    let stats = &block.stats;
+    if let Some(optimized) = &stats.optimized {
+        let gap = optimized.upper_bound.saturating_sub(optimized.fee);
+        println!("Greedy fee: {}", stats.selected_fee);
+        println!("Optimized fee: {} (+{})", optimized.fee, optimized.fee.saturating_sub(stats.selected_fee));
+        println!("Upper bound: {}", optimized.upper_bound);
+        println!("Gap: {} ({:.4}%)", gap, 100.0 * gap as f64 / optimized.upper_bound.max(1) as f64);
+        println!(
+            "Search: {} after {} nodes",
+            if optimized.complete { "complete, optimal" } else { "stopped by the time budget" },
+            optimized.nodes
+        );
+    }
+    println!(
+        "Gap fill: +{} fee, +{} weight in {} transactions{}",
+        stats.gap_fill.fee,
+        stats.gap_fill.weight,
+        stats.gap_fill.count,
+        if stats.gap_fill.complete { "" } else { " (stopped by the node budget)" }
+    );
+
+    //print the block data except the transactions
+    println!("Block weight: {}", block.weight);
+    println!("Block sigops: {}", block.sigops);
+    println!("Block fee: {}", block.fee);
+    if block.modified_fee != block.fee {
+        println!("Block modified fee: {}", block.modified_fee);
+    }
+    println!("Number of transactions: {}", block.txids.len());
+    println!("Packing efficiency: {}", block.packing_efficiency());
+
+    //write the block to a file
+    let output = options.output.as_deref().unwrap_or("../../block.txt");
+    let file = File::create(output).map_err(|e| format!("{}: {}", output, e))?;
+    block.write_to(BufWriter::new(file))?;
+    Ok(())
+}
+
+fn project(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mempool = load(options)?;
+    let projection = builder(options).project(&mempool, options.blocks);
+
+    let output = options.output.as_deref().unwrap_or("projection.csv");
+    let mut writer = BufWriter::new(File::create(output).map_err(|e| format!("{}: {}", output, e))?);
+    let mut projected = 0;
+    for (index, block) in projection.blocks.iter().enumerate() {
+        let modified = if block.modified_fee != block.fee {
+            format!(" (modified {})", block.modified_fee)
+        } else {
+            String::new()
+        };
+        println!(
+            "Block {}: {} transactions, weight {}, fee {}{}, min feerate {:.2} sat/vB, median feerate {:.2} sat/vB",
+            index,
+            block.txids.len(),
+            block.weight,
+            block.fee,
+            modified,
+            block.min_feerate.sat_per_vbyte(),
+            block.median_feerate.sat_per_vbyte()
+        );
+        for txid in &block.txids {
+            writeln!(writer, "{},{}", txid, index)?;
+        }
+        projected += block.txids.len();
+    }
+    writer.flush()?;
+    println!("Projected {} of {} transactions into {} blocks", projected, mempool.len(), projection.blocks.len());
+    Ok(())
+}
+
+fn verify(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mempool = load(options)?;
+    let contents = fs::read_to_string(&options.block).map_err(|e| format!("{}: {}", options.block, e))?;
+    let txids: Vec<String> = contents.lines().map(|line| line.trim().to_string()).collect();
+
+    let verification = rust::verify(&mempool, &txids, builder(options).limits());
+    for violation in &verification.violations {
+        println!("{}", violation);
+    }
+    println!("Block weight: {}", verification.weight);
+    println!("Block sigops: {}", verification.sigops);
+    println!("Block fee: {}", verification.fee);
+    println!("Number of transactions: {}", verification.count);
+    println!("Packing efficiency: {}", verification.packing_efficiency());
+
+    if !verification.is_valid() {
+        return Err(format!("{} is invalid: {} problems", options.block, verification.violations.len()).into());
+    }
+    println!("{} is valid", options.block);
+    Ok(())
+}
+
+fn bench(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mempool = load(options)?;
+    match options.size {
+        Some(size) => println!("Synthetic mempool: {} transactions, seed {}", size, options.seed),
+        None => println!("Mempool: {} transactions from {}", mempool.len(), options.input),
+    }
+    let budget = Duration::from_secs_f64(options.optimize.unwrap_or(1.0));
+    // the search is a variant of its own here rather than part of every run
+    let options = Options { optimize: None, ..options.clone() };
+    bench::run(&mempool, &builder(&options), budget);
+    Ok(())
+}
+
+fn generate(options: &Options) -> Result<(), Box<dyn Error>> {
+    let output = options.output.as_deref().unwrap_or("synthetic.csv");
+    fs::write(output, synthetic(options).to_csv()).map_err(|e| format!("{}: {}", output, e))?;
+    Ok(())
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let Some(options) = parse_args()? else {
+        return Ok(());
+    };
+    match options.command {
+        Command::Build => build(&options),
+        Command::Verify => verify(&options),
+        Command::Project => project(&options),
+        Command::Bench => bench(&options),
+        Command::Generate => generate(&options),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
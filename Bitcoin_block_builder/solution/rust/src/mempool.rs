@@ -0,0 +1,336 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::error::Error;
+use std::io::Read;
+
+use crate::feerate::FeeRate;
+use crate::limits::Limits;
+use crate::reader::{read_csv, read_json_lines};
+use crate::validate::{validate, Issue, Row, Validation};
+
+#[derive(Debug)]
+pub(crate) struct Data {
+    pub(crate) txid: String,
+    pub(crate) fee: u64,
+    pub(crate) weight: u64,
+    pub(crate) parents: Vec<String>,
+    pub(crate) sigops: u64,
+}
+
+#[derive(Clone, Eq, PartialEq)]
+pub(crate) struct TxNode {
+    pub(crate) txid: String,
+    pub(crate) fee: u64,
+    // the fee with any prioritisation applied, what selection ranks and maximizes by
+    pub(crate) modified_fee: u64,
+    // every delta prioritise added up, kept apart so the clamping of modified_fee at 0 and
+    // u64::MAX doesn't depend on the order deltas come in
+    pub(crate) fee_delta: i128,
+    pub(crate) weight: u64,
+    pub(crate) sigops: u64,
+}
+
+impl TxNode {
+    pub(crate) fn feerate(&self) -> FeeRate {
+        FeeRate::new(self.modified_fee, self.weight)
+    }
+}
+
+impl Ord for TxNode {
+
+    fn cmp(&self, other: &Self) -> Ordering {
+        // on equal feerates the smaller txid wins, so the heap pops in a fixed order
+        self.feerate().cmp(&other.feerate()).then_with(|| other.txid.cmp(&self.txid))
+    }
+}
+
+impl PartialOrd for TxNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// the mempool with transactions addressed by their position in the file
+#[derive(Clone)]
+pub struct Mempool {
+    pub(crate) nodes: Vec<TxNode>,
+    // parents in the mempool, and how many parents a transaction lists in all
+    pub(crate) parents: Vec<Vec<usize>>,
+    pub(crate) parent_count: Vec<usize>,
+    pub(crate) children: Vec<Vec<usize>>,
+    // where each txid sits
+    index: HashMap<String, usize>,
+    // rows left out of a lenient load
+    issues: Vec<Issue>,
+}
+
+impl Mempool {
+    // one `txid,fee,weight,parents` row per transaction, parents separated by `;`, with or
+    // without a header row
+    pub fn from_csv<R: Read>(reader: R, validation: Validation) -> Result<Mempool, Box<dyn Error>> {
+        Mempool::from_rows(read_csv(reader)?, validation)
+    }
+
+    // the same transactions as JSON objects, one per line
+    pub fn from_json_lines<R: Read>(reader: R, validation: Validation) -> Result<Mempool, Box<dyn Error>> {
+        Mempool::from_rows(read_json_lines(reader)?, validation)
+    }
+
+    fn from_rows(rows: Vec<Row>, validation: Validation) -> Result<Mempool, Box<dyn Error>> {
+        let (records, issues) = validate(rows, validation)?;
+        let mut mempool = Mempool::from_records(records);
+        mempool.issues = issues;
+        Ok(mempool)
+    }
+
+    fn from_records(records: Vec<Data>) -> Mempool {
+        let index: HashMap<String, usize> = records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| (record.txid.clone(), i))
+            .collect();
+
+        let mut mempool = Mempool {
+            nodes: Vec::new(),
+            parents: vec![Vec::new(); records.len()],
+            parent_count: vec![0; records.len()],
+            children: vec![Vec::new(); records.len()],
+            index: HashMap::new(),
+            issues: Vec::new(),
+        };
+        for (i, record) in records.into_iter().enumerate() {
+            mempool.parent_count[i] = record.parents.len();
+            for parent in &record.parents {
+                if let Some(&p) = index.get(parent) {
+                    mempool.parents[i].push(p);
+                    mempool.children[p].push(i);
+                }
+            }
+            mempool.nodes.push(TxNode {
+                txid: record.txid,
+                fee: record.fee,
+                modified_fee: record.fee,
+                fee_delta: 0,
+                weight: record.weight,
+                sigops: record.sigops,
+            });
+        }
+        mempool.index = index;
+        mempool
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // the transactions not in `mined`, in the same order. parents that were mined count
+    // as confirmed
+    pub(crate) fn remaining(&self, mined: &[bool]) -> Mempool {
+        let mut position = vec![usize::MAX; self.nodes.len()];
+        let mut mempool = Mempool {
+            nodes: Vec::new(),
+            parents: Vec::new(),
+            parent_count: Vec::new(),
+            children: Vec::new(),
+            index: HashMap::new(),
+            issues: Vec::new(),
+        };
+        for i in (0..self.nodes.len()).filter(|&i| !mined[i]) {
+            position[i] = mempool.nodes.len();
+            mempool.index.insert(self.nodes[i].txid.clone(), position[i]);
+            mempool.nodes.push(self.nodes[i].clone());
+            mempool.children.push(Vec::new());
+        }
+        for i in (0..self.nodes.len()).filter(|&i| !mined[i]) {
+            let parents: Vec<usize> = self.parents[i].iter().filter(|&&p| !mined[p]).map(|&p| position[p]).collect();
+            for &p in &parents {
+                mempool.children[p].push(position[i]);
+            }
+            // a parent that never made it into the mempool still holds the child back
+            let missing = self.parent_count[i] - self.parents[i].len();
+            mempool.parent_count.push(parents.len() + missing);
+            mempool.parents.push(parents);
+        }
+        mempool
+    }
+
+    // Bitcoin Core's prioritisetransaction: `delta` is added to the fee selection goes by,
+    // the fee the transaction pays stays as it is. deltas add up, and a modified fee below
+    // zero counts as zero. false when the txid isn't in the mempool: unlike Core, which keeps
+    // such a delta for when the transaction arrives, it is dropped, since nothing arrives in
+    // a mempool read from a file
+    pub fn prioritise(&mut self, txid: &str, delta: i64) -> bool {
+        let Some(&i) = self.index.get(txid) else {
+            return false;
+        };
+        let node = &mut self.nodes[i];
+        node.fee_delta += delta as i128;
+        node.modified_fee = (node.fee as i128 + node.fee_delta).clamp(0, u64::MAX as i128) as u64;
+        true
+    }
+
+    pub(crate) fn position(&self, txid: &str) -> Option<usize> {
+        self.index.get(txid).copied()
+    }
+
+    // what was wrong with the rows that were dropped, in file order
+    pub fn issues(&self) -> &[Issue] {
+        &self.issues
+    }
+
+    // a parent that isn't in the mempool can never be placed, so neither can the child, and
+    // the same goes for a parent paying less than `min_feerate`
+    pub(crate) fn eligible(&self, min_feerate: FeeRate) -> Vec<bool> {
+        let mut placeable: Vec<bool> = (0..self.nodes.len())
+            .map(|i| self.parents[i].len() == self.parent_count[i] && self.nodes[i].feerate() >= min_feerate)
+            .collect();
+        let mut stack: Vec<usize> = (0..self.nodes.len()).filter(|&i| !placeable[i]).collect();
+        while let Some(i) = stack.pop() {
+            for &child in &self.children[i] {
+                if placeable[child] {
+                    placeable[child] = false;
+                    stack.push(child);
+                }
+            }
+        }
+        placeable
+    }
+
+    // best feerate first among the transactions whose parents are all placed, restricted
+    // to `allowed`, skipping whatever no longer fits
+    pub(crate) fn pack(&self, allowed: &[bool], limits: &Limits) -> Vec<usize> {
+        self.extend(&[], allowed, limits)
+    }
+
+    // same as `pack`, appending to a block that already holds `placed`
+    pub(crate) fn extend(&self, placed: &[usize], allowed: &[bool], limits: &Limits) -> Vec<usize> {
+        let mut incomings = self.parent_count.clone();
+        for &i in placed {
+            for &child in &self.children[i] {
+                incomings[child] -= 1;
+            }
+        }
+        let mut heap: BinaryHeap<(&TxNode, usize)> = (0..self.nodes.len())
+            .filter(|&i| incomings[i] == 0 && allowed[i])
+            .map(|i| (&self.nodes[i], i))
+            .collect();
+
+        let mut block: Vec<usize> = placed.to_vec();
+        let mut block_weight: u64 = self.weight(placed);
+        let mut block_sigops: u64 = self.sigops(placed);
+        while let Some((node, i)) = heap.pop() {
+            if block_weight + node.weight > limits.weight() || block_sigops + node.sigops > limits.sigops() {
+                continue;
+            }
+            block_weight += node.weight;
+            block_sigops += node.sigops;
+            block.push(i);
+
+            for &child in &self.children[i] {
+                incomings[child] -= 1;
+                if incomings[child] == 0 && allowed[child] {
+                    heap.push((&self.nodes[child], child));
+                }
+            }
+        }
+        block
+    }
+
+    // whether a transaction outside `block` with all of its parents in it still fits. without
+    // one nothing can be added to the block, since whatever is added starts with one
+    pub(crate) fn can_extend(&self, block: &[usize], allowed: &[bool], limits: &Limits) -> bool {
+        let mut in_block = vec![false; self.nodes.len()];
+        for &i in block {
+            in_block[i] = true;
+        }
+        let weight_left = limits.weight().saturating_sub(self.weight(block));
+        let sigops_left = limits.sigops().saturating_sub(self.sigops(block));
+        (0..self.nodes.len()).any(|i| {
+            allowed[i]
+                && !in_block[i]
+                && self.parents[i].iter().all(|&p| in_block[p])
+                && self.nodes[i].weight <= weight_left
+                && self.nodes[i].sigops <= sigops_left
+        })
+    }
+
+    // fees run up to u64::MAX each, and a prioritised one may be that already, so the sums
+    // stop there rather than wrap
+    pub(crate) fn fee(&self, block: &[usize]) -> u64 {
+        block.iter().fold(0u64, |sum, &i| sum.saturating_add(self.nodes[i].fee))
+    }
+
+    pub(crate) fn modified_fee(&self, block: &[usize]) -> u64 {
+        block.iter().fold(0u64, |sum, &i| sum.saturating_add(self.nodes[i].modified_fee))
+    }
+
+    pub(crate) fn weight(&self, block: &[usize]) -> u64 {
+        block.iter().map(|&i| self.nodes[i].weight).sum()
+    }
+
+    pub(crate) fn sigops(&self, block: &[usize]) -> u64 {
+        block.iter().map(|&i| self.nodes[i].sigops).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txid(c: char) -> String {
+        c.to_string().repeat(64)
+    }
+
+    #[test]
+    fn test_remaining_after_a_block() {
+        // c spends b, which spends a. d spends e, which isn't in the mempool, and is dropped
+        let (a, b, c, d, e) = (txid('a'), txid('b'), txid('c'), txid('d'), txid('e'));
+        let csv = format!("{a},100,400,\n{b},100,400,{a}\n{c},100,400,{b}\n{d},100,400,{e}\n");
+        let mempool = Mempool::from_csv(csv.as_bytes(), Validation::Lenient).unwrap();
+        assert_eq!(mempool.len(), 3);
+
+        let remaining = mempool.remaining(&[true, false, false]);
+        let txids: Vec<&str> = remaining.nodes.iter().map(|node| node.txid.as_str()).collect();
+        assert_eq!(txids, vec![b.as_str(), c.as_str()]);
+        // a is confirmed, b has nothing left to wait for
+        assert_eq!(remaining.parents, vec![vec![], vec![0]]);
+        assert_eq!(remaining.parent_count, vec![0, 1]);
+        assert_eq!(remaining.children, vec![vec![1], vec![]]);
+        assert_eq!(remaining.eligible(FeeRate::new(0, 1)), vec![true, true]);
+    }
+
+    #[test]
+    fn test_prioritise() {
+        let (a, b) = (txid('a'), txid('b'));
+        let csv = format!("{a},1000,400,\n{b},{},400,\n", u64::MAX - 10);
+        let mut mempool = Mempool::from_csv(csv.as_bytes(), Validation::Strict).unwrap();
+        let modified = |mempool: &Mempool| mempool.nodes.iter().map(|node| node.modified_fee).collect::<Vec<_>>();
+
+        // a fee taken below zero counts as zero, and the delta that did it still counts after
+        assert!(mempool.prioritise(&a, -5000));
+        assert_eq!(modified(&mempool), vec![0, u64::MAX - 10]);
+        assert!(mempool.prioritise(&a, 4500));
+        assert_eq!(modified(&mempool), vec![500, u64::MAX - 10]);
+
+        // past u64::MAX is u64::MAX, the same way
+        assert!(mempool.prioritise(&b, i64::MAX));
+        assert!(mempool.prioritise(&b, i64::MAX));
+        assert_eq!(modified(&mempool)[1], u64::MAX);
+        assert!(mempool.prioritise(&b, i64::MIN));
+        assert!(mempool.prioritise(&b, i64::MIN));
+        assert_eq!(modified(&mempool)[1], u64::MAX - 12);
+        assert_eq!(mempool.nodes[1].fee, u64::MAX - 10);
+
+        // a txid that isn't in the mempool changes nothing, now or once mined ones are gone
+        assert!(!mempool.prioritise(&txid('c'), 1000));
+        assert_eq!(modified(&mempool), vec![500, u64::MAX - 12]);
+        let mut remaining = mempool.remaining(&[true, false]);
+        assert!(!remaining.prioritise(&a, 1000));
+        assert!(remaining.prioritise(&b, 12));
+        assert_eq!(modified(&remaining), vec![u64::MAX]);
+    }
+}
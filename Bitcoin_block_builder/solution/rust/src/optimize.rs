@@ -0,0 +1,426 @@
+use std::time::{Duration, Instant};
+
+use crate::limits::Limits;
+use crate::mempool::Mempool;
+
+const UNDECIDED: u8 = 0;
+const INCLUDED: u8 = 1;
+const EXCLUDED: u8 = 2;
+
+// how far a search may go before it settles for the best block found so far
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Budget {
+    // wall clock time, what the search gets to depends on the machine
+    Time(Duration),
+    // search tree nodes, the same block on every run
+    Nodes(u64),
+}
+
+// outcome of the branch and bound search, in modified fees like everything the search weighs
+pub(crate) struct Optimized {
+    pub(crate) block: Vec<usize>,
+    pub(crate) fee: u64,
+    // no block from this mempool can collect more than this
+    pub(crate) upper_bound: u64,
+    // the whole tree was searched, so `fee` is the optimum
+    pub(crate) complete: bool,
+    pub(crate) nodes: u64,
+}
+
+// branch and bound over include / exclude decisions taken in feerate order. including a
+// transaction includes its ancestors and excluding one excludes its descendants, so every
+// leaf is a set closed under parents. the bound at a node is the LP relaxation of the
+// knapsack over the undecided transactions with the parent constraints dropped
+struct Search<'a> {
+    mempool: &'a Mempool,
+    limits: Limits,
+    order: Vec<usize>,
+    status: Vec<u8>,
+    // transactions whose status changed, undone when backtracking
+    trail: Vec<usize>,
+    // fees are added up wider than u64 so that undoing an include takes back exactly what it
+    // added, however large the prioritised fees
+    fee: u128,
+    weight: u64,
+    sigops: u64,
+    best: Vec<bool>,
+    best_fee: u128,
+    // highest bound among the subtrees left unexplored when the budget ran out
+    open_bound: u128,
+    deadline: Option<Instant>,
+    max_nodes: u64,
+    out_of_budget: bool,
+    nodes: u64,
+}
+
+impl<'a> Search<'a> {
+    fn include(&mut self, i: usize) -> bool {
+        let mut pending: Vec<usize> = Vec::new();
+        let mut stack = vec![i];
+        while let Some(j) = stack.pop() {
+            match self.status[j] {
+                INCLUDED => continue,
+                EXCLUDED => return false,
+                _ => {}
+            }
+            if pending.contains(&j) {
+                continue;
+            }
+            pending.push(j);
+            stack.extend(self.mempool.parents[j].iter().copied());
+        }
+
+        if self.weight + self.mempool.weight(&pending) > self.limits.weight()
+            || self.sigops + self.mempool.sigops(&pending) > self.limits.sigops()
+        {
+            return false;
+        }
+        for j in pending {
+            self.status[j] = INCLUDED;
+            self.fee += self.mempool.nodes[j].modified_fee as u128;
+            self.weight += self.mempool.nodes[j].weight;
+            self.sigops += self.mempool.nodes[j].sigops;
+            self.trail.push(j);
+        }
+        true
+    }
+
+    fn exclude(&mut self, i: usize) {
+        let mut stack = vec![i];
+        while let Some(j) = stack.pop() {
+            if self.status[j] != UNDECIDED {
+                continue;
+            }
+            self.status[j] = EXCLUDED;
+            self.trail.push(j);
+            stack.extend(self.mempool.children[j].iter().copied());
+        }
+    }
+
+    fn undo(&mut self, mark: usize) {
+        while self.trail.len() > mark {
+            let j = self.trail.pop().unwrap();
+            if self.status[j] == INCLUDED {
+                self.fee -= self.mempool.nodes[j].modified_fee as u128;
+                self.weight -= self.mempool.nodes[j].weight;
+                self.sigops -= self.mempool.nodes[j].sigops;
+            }
+            self.status[j] = UNDECIDED;
+        }
+    }
+
+    // fee so far plus the undecided transactions by feerate, the last one taken fractionally.
+    // sigops are left out, which only makes the bound looser
+    fn bound(&self, pos: usize) -> u128 {
+        let mut bound = self.fee;
+        let mut room = self.limits.weight() - self.weight;
+        for &i in &self.order[pos..] {
+            if self.status[i] != UNDECIDED {
+                continue;
+            }
+            let node = &self.mempool.nodes[i];
+            if node.weight <= room {
+                bound += node.modified_fee as u128;
+                room -= node.weight;
+            } else {
+                bound += node.modified_fee as u128 * room as u128 / node.weight as u128;
+                break;
+            }
+        }
+        bound
+    }
+
+    fn record(&mut self) {
+        if self.fee > self.best_fee {
+            self.best_fee = self.fee;
+            self.best = self.status.iter().map(|s| *s == INCLUDED).collect();
+        }
+    }
+
+    // arrives at the tree node for the first undecided transaction from `pos` on that fits,
+    // a frame when it has to be branched on. the ones passed over can't go in anywhere below,
+    // the room only gets smaller, and stay out of the bound
+    fn enter(&mut self, mut pos: usize) -> Option<Frame> {
+        let weight_left = self.limits.weight() - self.weight;
+        let sigops_left = self.limits.sigops().saturating_sub(self.sigops);
+        while pos < self.order.len() {
+            let node = &self.mempool.nodes[self.order[pos]];
+            if self.status[self.order[pos]] == UNDECIDED && node.weight <= weight_left && node.sigops <= sigops_left {
+                break;
+            }
+            pos += 1;
+        }
+        self.nodes += 1;
+
+        let bound = self.bound(pos);
+        if bound <= self.best_fee {
+            return None;
+        }
+        if self.out_of_budget || self.nodes > self.max_nodes || self.deadline.is_some_and(|d| Instant::now() >= d) {
+            self.out_of_budget = true;
+            self.open_bound = self.open_bound.max(bound);
+            return None;
+        }
+        // nothing undecided fits any more
+        if pos == self.order.len() || bound == self.fee {
+            self.record();
+            return None;
+        }
+        Some(Frame { pos, mark: self.trail.len(), step: Step::Include })
+    }
+
+    // depth first, the include branch before the exclude one. the path is kept in an explicit
+    // stack rather than on the call stack, it is as deep as there are transactions
+    fn search(&mut self) {
+        let mut stack: Vec<Frame> = self.enter(0).into_iter().collect();
+        while let Some(frame) = stack.last_mut() {
+            let (pos, mark) = (frame.pos, frame.mark);
+            let i = self.order[pos];
+            match frame.step {
+                Step::Include => {
+                    frame.step = Step::Exclude;
+                    if self.include(i) {
+                        stack.extend(self.enter(pos + 1));
+                    }
+                }
+                Step::Exclude => {
+                    frame.step = Step::Done;
+                    self.undo(mark);
+                    self.exclude(i);
+                    if self.out_of_budget {
+                        let bound = self.bound(pos + 1);
+                        if bound > self.best_fee {
+                            self.open_bound = self.open_bound.max(bound);
+                        }
+                    } else {
+                        stack.extend(self.enter(pos + 1));
+                    }
+                }
+                Step::Done => {
+                    self.undo(mark);
+                    stack.pop();
+                }
+            }
+        }
+    }
+}
+
+// modified fee of `block` as the search adds it up
+fn total_fee(mempool: &Mempool, block: &[usize]) -> u128 {
+    block.iter().map(|&i| mempool.nodes[i].modified_fee as u128).sum()
+}
+
+// which branch of a tree node is next
+enum Step {
+    Include,
+    Exclude,
+    Done,
+}
+
+// a tree node on the path being searched, `mark` is where the trail stood on arriving
+struct Frame {
+    pos: usize,
+    mark: usize,
+    step: Step,
+}
+
+impl<'a> Search<'a> {
+    // a search over every placeable transaction but those of `fixed`, which are in already
+    fn new(mempool: &'a Mempool, limits: &Limits, fixed: &[usize], budget: Budget) -> Search<'a> {
+        let placeable = mempool.eligible(limits.min_feerate);
+        let mut status: Vec<u8> = placeable.iter().map(|&p| if p { UNDECIDED } else { EXCLUDED }).collect();
+        for &i in fixed {
+            status[i] = INCLUDED;
+        }
+
+        let mut order: Vec<usize> = (0..mempool.nodes.len()).filter(|&i| status[i] == UNDECIDED).collect();
+        order.sort_by(|&a, &b| mempool.nodes[b].cmp(&mempool.nodes[a]));
+
+        Search {
+            mempool,
+            limits: *limits,
+            order,
+            best: status.iter().map(|s| *s == INCLUDED).collect(),
+            status,
+            trail: Vec::new(),
+            fee: total_fee(mempool, fixed),
+            weight: mempool.weight(fixed),
+            sigops: mempool.sigops(fixed),
+            best_fee: total_fee(mempool, fixed),
+            open_bound: 0,
+            deadline: match budget {
+                Budget::Time(time) => Some(Instant::now() + time),
+                Budget::Nodes(_) => None,
+            },
+            max_nodes: match budget {
+                Budget::Time(_) => u64::MAX,
+                Budget::Nodes(nodes) => nodes,
+            },
+            out_of_budget: false,
+            nodes: 0,
+        }
+    }
+
+    fn run(mut self) -> (Vec<bool>, u64, bool, u64) {
+        self.search();
+        let upper_bound = self.best_fee.max(self.open_bound).min(u64::MAX as u128) as u64;
+        (self.best, upper_bound, !self.out_of_budget, self.nodes)
+    }
+}
+
+// improves on `start_block` for as long as `budget` allows
+pub(crate) fn branch_and_bound(mempool: &Mempool, limits: &Limits, start_block: &[usize], budget: Duration) -> Optimized {
+    let mut search = Search::new(mempool, limits, &[], Budget::Time(budget));
+    for &i in start_block {
+        search.best[i] = true;
+    }
+    search.best_fee = total_fee(mempool, start_block);
+    let (best, upper_bound, complete, nodes) = search.run();
+
+    // the chosen set is closed under parents, so it all fits in parent first order
+    let block = mempool.pack(&best, limits);
+    Optimized {
+        fee: mempool.modified_fee(&block),
+        block,
+        upper_bound,
+        complete,
+        nodes,
+    }
+}
+
+// keeps `block` as it is and searches the weight it leaves for the most fee, among every
+// transaction whose parents are in the block or come along, skipped ones included. a greedy
+// pass that ran to the end leaves nothing that fits, this pays off after a selection that
+// stopped before it ran out of candidates
+pub(crate) fn fill_gap(mempool: &Mempool, limits: &Limits, block: &[usize], budget: Budget) -> Optimized {
+    let (best, upper_bound, complete, nodes) = Search::new(mempool, limits, block, budget).run();
+
+    let mut additions = best;
+    for &i in block {
+        additions[i] = false;
+    }
+    let block = mempool.extend(block, &additions, limits);
+    Optimized {
+        fee: mempool.modified_fee(&block),
+        block,
+        upper_bound,
+        complete,
+        nodes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::MAX_BLOCK_WEIGHT;
+    use crate::synthetic::{FeeDistribution, Synthetic};
+    use crate::validate::Validation;
+
+    fn txid(c: char) -> String {
+        c.to_string().repeat(64)
+    }
+
+    // greedy takes c and d and has no room left for a, which is all b needs to be worth more
+    // than both: a, b and c are the best block
+    fn mempool() -> (Mempool, Limits) {
+        let (a, b, c, d) = (txid('a'), txid('b'), txid('c'), txid('d'));
+        let csv = format!("{a},10,400,\n{b},1000,200,{a}\n{c},700,400,\n{d},390,400,\n");
+        let mut limits = Limits::new(1000);
+        limits.reserved_weight = 0;
+        (Mempool::from_csv(csv.as_bytes(), Validation::Strict).unwrap(), limits)
+    }
+
+    // every parent of a transaction in the block is in it, ahead of the transaction
+    fn parents_first(mempool: &Mempool, block: &[usize]) -> bool {
+        block.iter().enumerate().all(|(k, &i)| mempool.parents[i].iter().all(|p| block[..k].contains(p)))
+    }
+
+    #[test]
+    fn test_branch_and_bound_beats_greedy() {
+        let (mempool, limits) = mempool();
+        let greedy = mempool.pack(&mempool.eligible(limits.min_feerate), &limits);
+        assert_eq!(mempool.modified_fee(&greedy), 1090);
+
+        let optimized = branch_and_bound(&mempool, &limits, &greedy, Duration::from_secs(60));
+        assert!(optimized.complete);
+        assert_eq!(optimized.fee, 1710);
+        assert_eq!(optimized.upper_bound, optimized.fee);
+        assert!(parents_first(&mempool, &optimized.block));
+        let mut block = optimized.block.clone();
+        block.sort();
+        assert_eq!(block, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_branch_and_bound_out_of_time() {
+        let (mempool, limits) = mempool();
+        let greedy = mempool.pack(&mempool.eligible(limits.min_feerate), &limits);
+
+        // no time at all leaves the tree unsearched, the start block stands and the bound
+        // still covers the optimum
+        let optimized = branch_and_bound(&mempool, &limits, &greedy, Duration::ZERO);
+        assert!(!optimized.complete);
+        assert_eq!(optimized.fee, 1090);
+        assert!(optimized.upper_bound >= 1710);
+        assert!(parents_first(&mempool, &optimized.block));
+    }
+
+    #[test]
+    fn test_fill_gap() {
+        let (mempool, limits) = mempool();
+
+        // the room c leaves is worth more to a and b together than to d
+        let filled = fill_gap(&mempool, &limits, &[2], Budget::Nodes(1000));
+        assert!(filled.complete);
+        assert_eq!(filled.block, vec![2, 0, 1]);
+        assert_eq!(filled.fee, 1710);
+
+        // greedy ran to the end, nothing it skipped fits
+        let greedy = mempool.pack(&mempool.eligible(limits.min_feerate), &limits);
+        let filled = fill_gap(&mempool, &limits, &greedy, Budget::Nodes(1000));
+        assert!(filled.complete);
+        assert_eq!(filled.block, greedy);
+
+        // there is room for a and b after c alone, there is none after greedy
+        let allowed = mempool.eligible(limits.min_feerate);
+        assert!(mempool.can_extend(&[2], &allowed, &limits));
+        assert!(!mempool.can_extend(&greedy, &allowed, &limits));
+
+        // out of nodes before the first one, the block stays as it is
+        let filled = fill_gap(&mempool, &limits, &[2], Budget::Nodes(0));
+        assert!(!filled.complete);
+        assert_eq!(filled.block, vec![2]);
+    }
+
+    // deep enough that a search recursing once per transaction overflows the stack of a test
+    // thread
+    fn large_mempool() -> Mempool {
+        let synthetic = Synthetic { size: 200_000, max_depth: 25, fees: FeeDistribution::Exponential { mean: 10.0 }, seed: 1 };
+        Mempool::from_csv(synthetic.to_csv().as_bytes(), Validation::Strict).unwrap()
+    }
+
+    #[test]
+    fn test_fill_gap_large_mempool() {
+        let mempool = large_mempool();
+        let limits = Limits::new(MAX_BLOCK_WEIGHT);
+        let filled = fill_gap(&mempool, &limits, &[], Budget::Nodes(10_000));
+        assert!(!filled.complete);
+        assert!(filled.upper_bound >= filled.fee && filled.fee > 0);
+        assert!(parents_first(&mempool, &filled.block));
+    }
+
+    #[test]
+    fn test_branch_and_bound_large_mempool() {
+        let mempool = large_mempool();
+        let limits = Limits::new(MAX_BLOCK_WEIGHT);
+        let greedy = mempool.pack(&mempool.eligible(limits.min_feerate), &limits);
+        let greedy_fee = mempool.fee(&greedy);
+
+        // a search started on greedy never does worse than it, and stops on time rather than
+        // on the stack
+        let optimized = branch_and_bound(&mempool, &limits, &greedy, Duration::from_millis(200));
+        assert!(optimized.fee >= greedy_fee);
+        assert!(optimized.upper_bound >= optimized.fee);
+        assert!(parents_first(&mempool, &optimized.block));
+    }
+}
@@ -0,0 +1,248 @@
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read};
+
+use serde_derive::Deserialize;
+
+use crate::mempool::Data;
+use crate::validate::Row;
+
+// where each field sits in a CSV row, the assignment order unless a header says otherwise
+struct Columns {
+    txid: usize,
+    fee: usize,
+    weight: usize,
+    parents: Option<usize>,
+    sigops: Option<usize>,
+}
+
+impl Columns {
+    const ASSIGNMENT: Columns = Columns { txid: 0, fee: 1, weight: 2, parents: Some(3), sigops: Some(4) };
+
+    // a first row naming txid, fee and weight is a header, anything else is data
+    fn from_header(record: &csv::StringRecord) -> Option<Columns> {
+        let position = |name: &str| record.iter().position(|field| field.trim().eq_ignore_ascii_case(name));
+        Some(Columns {
+            txid: position("txid")?,
+            fee: position("fee")?,
+            weight: position("weight")?,
+            parents: position("parents"),
+            sigops: position("sigops"),
+        })
+    }
+
+    fn parse(&self, record: &csv::StringRecord) -> Result<Data, String> {
+        // columns after the last required one may be left off a row that has nothing in them
+        let required = self.txid.max(self.fee).max(self.weight) + 1;
+        let width = [self.parents, self.sigops].into_iter().flatten().fold(required, |w, c| w.max(c + 1));
+        if record.len() < required || record.len() > width {
+            return Err(format!("expected {} to {} fields, found {}", required, width, record.len()));
+        }
+
+        let field = |column: Option<usize>| column.and_then(|c| record.get(c)).unwrap_or("").trim();
+        let number = |column: usize, name: &str| {
+            let field = field(Some(column));
+            field.parse::<u64>().map_err(|_| format!("{} is not a number: {:?}", name, field))
+        };
+        Ok(Data {
+            txid: field(Some(self.txid)).to_string(),
+            fee: number(self.fee, "fee")?,
+            weight: number(self.weight, "weight")?,
+            parents: parse_parents(field(self.parents)),
+            // no sigops column means the transactions don't count against the budget
+            sigops: match self.sigops {
+                Some(column) if !field(Some(column)).is_empty() => number(column, "sigops")?,
+                _ => 0,
+            },
+        })
+    }
+}
+
+// parents are `;`-separated within their one field. an empty field means no parents, an empty
+// entry between separators is kept so validation reports it
+pub(crate) fn parse_parents(field: &str) -> Vec<String> {
+    let field = field.trim();
+    if field.is_empty() {
+        return Vec::new();
+    }
+    field.split(';').map(|parent| parent.trim().to_string()).collect()
+}
+
+// `txid,fee,weight,parents[,sigops]` rows, optionally under a header row naming the columns
+pub(crate) fn read_csv<R: Read>(reader: R) -> Result<Vec<Row>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+
+    let mut columns = None;
+    let mut rows: Vec<Row> = Vec::new();
+    for (number, record) in rdr.records().enumerate() {
+        let record = record?;
+        let line = record.position().map_or(number as u64 + 1, |p| p.line());
+        if number == 0 {
+            if let Some(header) = Columns::from_header(&record) {
+                columns = Some(header);
+                continue;
+            }
+        }
+        let data = columns.as_ref().unwrap_or(&Columns::ASSIGNMENT).parse(&record);
+        rows.push(Row { line, data });
+    }
+    Ok(rows)
+}
+
+// `txid,fee_delta` rows for Mempool::prioritise, optionally under a header row. any bad row
+// fails the whole file, a delta quietly left out would be worse
+pub fn read_fee_deltas<R: Read>(reader: R) -> Result<Vec<(String, i64)>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+
+    let mut deltas: Vec<(String, i64)> = Vec::new();
+    for (number, record) in rdr.records().enumerate() {
+        let record = record?;
+        let line = record.position().map_or(number as u64 + 1, |p| p.line());
+        if number == 0 && record.get(0).is_some_and(|field| field.trim().eq_ignore_ascii_case("txid")) {
+            continue;
+        }
+        if record.len() != 2 {
+            return Err(format!("line {}: expected txid,fee_delta", line).into());
+        }
+        let delta = record[1].trim();
+        let delta = delta.parse::<i64>().map_err(|_| format!("line {}: fee delta is not a number: {:?}", line, delta))?;
+        deltas.push((record[0].trim().to_string(), delta));
+    }
+    Ok(deltas)
+}
+
+#[derive(Deserialize)]
+struct JsonRow {
+    txid: String,
+    fee: u64,
+    weight: u64,
+    #[serde(default)]
+    parents: Vec<String>,
+    #[serde(default)]
+    sigops: u64,
+}
+
+// one `{"txid", "fee", "weight", "parents": [...], "sigops"}` object per line, blank lines
+// skipped
+pub(crate) fn read_json_lines<R: Read>(reader: R) -> Result<Vec<Row>, Box<dyn Error>> {
+    let mut rows: Vec<Row> = Vec::new();
+    for (number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let data = serde_json::from_str::<JsonRow>(&line)
+            .map(|row| Data {
+                txid: row.txid,
+                fee: row.fee,
+                weight: row.weight,
+                parents: row.parents,
+                sigops: row.sigops,
+            })
+            .map_err(|e| e.to_string());
+        rows.push(Row { line: number as u64 + 1, data });
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txid(c: char) -> String {
+        c.to_string().repeat(64)
+    }
+
+    fn parsed(rows: Vec<Row>) -> Vec<(u64, String, u64, u64, Vec<String>)> {
+        rows.into_iter()
+            .map(|row| {
+                let data = row.data.unwrap();
+                (row.line, data.txid, data.fee, data.weight, data.parents)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_semicolon_separated_parents() {
+        let (a, b, c) = (txid('a'), txid('b'), txid('c'));
+        let csv = format!("{a},100,400,\n{b},200,800,{a}\n{c},300,1200,{a};{b}\n");
+        assert_eq!(
+            parsed(read_csv(csv.as_bytes()).unwrap()),
+            vec![
+                (1, a.clone(), 100, 400, vec![]),
+                (2, b.clone(), 200, 800, vec![a.clone()]),
+                (3, c, 300, 1200, vec![a.clone(), b]),
+            ]
+        );
+        assert_eq!(parse_parents(&format!("{a};;")), vec![a, String::new(), String::new()]);
+    }
+
+    #[test]
+    fn test_optional_header_row() {
+        let (a, b) = (txid('a'), txid('b'));
+        let plain = format!("{a},100,400,\n{b},200,800,{a}\n");
+        let header = format!("txid,fee,weight,parents\n{plain}");
+        let rows = parsed(read_csv(header.as_bytes()).unwrap());
+        assert_eq!(rows[0], (2, a.clone(), 100, 400, vec![]));
+        assert_eq!(rows.len(), 2);
+
+        // columns are found by name, and a file without parents can leave the column out
+        let reordered = format!("weight,txid,fee\n400,{a},100\n");
+        assert_eq!(parsed(read_csv(reordered.as_bytes()).unwrap()), vec![(2, a, 100, 400, vec![])]);
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let (a, b) = (txid('a'), txid('b'));
+        let jsonl = format!(
+            "{{\"txid\":\"{a}\",\"fee\":100,\"weight\":400}}\n\n{{\"txid\":\"{b}\",\"fee\":200,\"weight\":800,\"parents\":[\"{a}\"]}}\n"
+        );
+        assert_eq!(
+            parsed(read_json_lines(jsonl.as_bytes()).unwrap()),
+            vec![(1, a.clone(), 100, 400, vec![]), (3, b, 200, 800, vec![a])]
+        );
+    }
+
+    #[test]
+    fn test_sigops_column() {
+        let (a, b) = (txid('a'), txid('b'));
+        let sigops = |rows: Vec<Row>| rows.into_iter().map(|row| row.data.unwrap().sigops).collect::<Vec<_>>();
+        let csv = format!("{a},100,400,,8\n{b},200,800,{a}\n");
+        assert_eq!(sigops(read_csv(csv.as_bytes()).unwrap()), vec![8, 0]);
+        let header = format!("sigops,txid,fee,weight\n4,{a},100,400\n");
+        assert_eq!(sigops(read_csv(header.as_bytes()).unwrap()), vec![4]);
+        let jsonl = format!("{{\"txid\":\"{a}\",\"fee\":100,\"weight\":400,\"sigops\":2}}\n");
+        assert_eq!(sigops(read_json_lines(jsonl.as_bytes()).unwrap()), vec![2]);
+    }
+
+    #[test]
+    fn test_fee_deltas() {
+        let (a, b) = (txid('a'), txid('b'));
+        let csv = format!("txid,fee_delta\n{a},1000\n{b}, -250\n");
+        assert_eq!(read_fee_deltas(csv.as_bytes()).unwrap(), vec![(a.clone(), 1000), (b, -250)]);
+        assert!(read_fee_deltas(format!("{a},lots\n").as_bytes()).is_err());
+        assert!(read_fee_deltas(format!("{a}\n").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_malformed_rows() {
+        let a = txid('a');
+        let csv = format!("{a},lots,400,\n{a},100\n{a},100,400,,extra\n{a},100,400,,1,2\n");
+        let errors: Vec<String> = read_csv(csv.as_bytes()).unwrap().into_iter().map(|row| row.data.unwrap_err()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "fee is not a number: \"lots\"".to_string(),
+                "expected 3 to 5 fields, found 2".to_string(),
+                "sigops is not a number: \"extra\"".to_string(),
+                "expected 3 to 5 fields, found 6".to_string(),
+            ]
+        );
+        assert!(read_json_lines("{\"txid\":1}\n".as_bytes()).unwrap()[0].data.is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+// feerates of the generated transactions in sat/vB
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeeDistribution {
+    Uniform { min: f64, max: f64 },
+    // most transactions near the floor of 1 sat/vB, a long tail above it
+    Exponential { mean: f64 },
+}
+
+// `uniform:MIN:MAX` or `exponential:MEAN`
+impl FromStr for FeeDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FeeDistribution, String> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let number = |part: &str| part.parse::<f64>().map_err(|_| format!("bad feerate {} in {}", part, s));
+        match parts.as_slice() {
+            ["uniform", min, max] => Ok(FeeDistribution::Uniform { min: number(min)?, max: number(max)? }),
+            ["exponential", mean] => Ok(FeeDistribution::Exponential { mean: number(mean)? }),
+            _ => Err(format!("expected uniform:MIN:MAX or exponential:MEAN, found {}", s)),
+        }
+    }
+}
+
+// a made up mempool, the same for the same seed
+#[derive(Clone, Debug)]
+pub struct Synthetic {
+    pub size: usize,
+    // longest chain of unconfirmed ancestors a transaction may have, 1 for no chains at all
+    pub max_depth: usize,
+    pub fees: FeeDistribution,
+    pub seed: u64,
+}
+
+// splitmix64, enough for test data and with no crate to pull in
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+impl Synthetic {
+    // rows in the mempool.csv format, every parent before its children
+    pub fn to_csv(&self) -> String {
+        let mut rng = Rng(self.seed);
+        let mut txids: Vec<String> = Vec::with_capacity(self.size);
+        let mut depths: Vec<usize> = Vec::with_capacity(self.size);
+        let mut csv = String::new();
+        for i in 0..self.size {
+            // the index keeps txids apart whatever the generator draws
+            let txid = format!("{:016x}{:016x}{:016x}{:016x}", rng.next(), rng.next(), rng.next(), i);
+
+            // typical transactions are a few hundred vbytes, one in twenty is a lot larger
+            let vsize = if rng.below(20) == 0 { 1000 + rng.below(24000) } else { 110 + rng.below(900) };
+            let feerate = match self.fees {
+                FeeDistribution::Uniform { min, max } => min + (max - min) * rng.unit(),
+                FeeDistribution::Exponential { mean } => 1.0 + (mean - 1.0).max(0.0) * -(1.0 - rng.unit()).ln(),
+            };
+            let fee = (feerate * vsize as f64).round() as u64;
+
+            // about a third spend one or two outputs of recent transactions, which makes for
+            // chains, as long as the parent isn't already as deep as they may go
+            let mut parents: Vec<usize> = Vec::new();
+            if i > 0 && self.max_depth > 1 && rng.below(3) == 0 {
+                for _ in 0..1 + rng.below(2) {
+                    let parent = i - 1 - rng.below(i.min(50));
+                    if depths[parent] < self.max_depth && !parents.contains(&parent) {
+                        parents.push(parent);
+                    }
+                }
+            }
+            let depth = 1 + parents.iter().map(|&p| depths[p]).max().unwrap_or(0);
+
+            let parents: Vec<&str> = parents.iter().map(|&p| txids[p].as_str()).collect();
+            writeln!(csv, "{},{},{},{}", txid, fee, 4 * vsize, parents.join(";")).unwrap();
+            txids.push(txid);
+            depths.push(depth);
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::Mempool;
+    use crate::validate::Validation;
+
+    fn synthetic(seed: u64) -> Synthetic {
+        Synthetic { size: 500, max_depth: 3, fees: FeeDistribution::Uniform { min: 1.0, max: 20.0 }, seed }
+    }
+
+    #[test]
+    fn test_same_seed_same_mempool() {
+        assert_eq!(synthetic(1).to_csv(), synthetic(1).to_csv());
+        assert_ne!(synthetic(1).to_csv(), synthetic(2).to_csv());
+
+        // it loads without a problem, chains no deeper than asked for
+        let mempool = Mempool::from_csv(synthetic(1).to_csv().as_bytes(), Validation::Strict).unwrap();
+        assert_eq!(mempool.len(), 500);
+        let mut depths = vec![0; mempool.len()];
+        for i in 0..mempool.len() {
+            depths[i] = 1 + mempool.parents[i].iter().map(|&p| depths[p]).max().unwrap_or(0);
+        }
+        assert_eq!(depths.iter().max(), Some(&3));
+    }
+
+    #[test]
+    fn test_fee_distribution_from_str() {
+        assert_eq!("uniform:1:20".parse(), Ok(FeeDistribution::Uniform { min: 1.0, max: 20.0 }));
+        assert_eq!("exponential:10".parse(), Ok(FeeDistribution::Exponential { mean: 10.0 }));
+        assert!("normal:1".parse::<FeeDistribution>().is_err());
+        assert!("uniform:1".parse::<FeeDistribution>().is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::mempool::Data;
+
+// what to do with rows that can't go into a block as they are
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Validation {
+    // any problem fails the whole load
+    Strict,
+    // offending rows and everything that depends on them are dropped
+    Lenient,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    // the row isn't txid,fee,weight,parents
+    Malformed(String),
+    Duplicate { first_line: u64 },
+    ZeroWeight,
+    // an entry of the parents field that isn't a txid
+    MalformedParents(String),
+    UnknownParent(String),
+    // the transaction is its own ancestor
+    Cycle,
+    // a parent was dropped for a problem of its own
+    DroppedParent(String),
+}
+
+// a problem and the row of the file it was found on, lines counted from 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issue {
+    pub line: u64,
+    pub txid: String,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.problem {
+            Problem::Malformed(e) => write!(f, "malformed row: {}", e),
+            Problem::Duplicate { first_line } => {
+                write!(f, "{} already appeared on line {}", self.txid, first_line)
+            }
+            Problem::ZeroWeight => write!(f, "{} has zero weight", self.txid),
+            Problem::MalformedParents(entry) => {
+                write!(f, "{} lists a parent that isn't a txid: {:?}", self.txid, entry)
+            }
+            Problem::UnknownParent(parent) => {
+                write!(f, "{} spends {}, which isn't in the mempool", self.txid, parent)
+            }
+            Problem::Cycle => write!(f, "{} is its own ancestor", self.txid),
+            Problem::DroppedParent(parent) => {
+                write!(f, "{} spends {}, which was dropped", self.txid, parent)
+            }
+        }
+    }
+}
+
+fn is_txid(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// a row as read from the file, before it is checked
+pub(crate) struct Row {
+    pub(crate) line: u64,
+    pub(crate) data: Result<Data, String>,
+}
+
+// the rows that are fit for a block, in file order, and what was wrong with the others.
+// in lenient mode the descendants of a dropped row go too, since they can never be placed
+pub(crate) fn validate(rows: Vec<Row>, validation: Validation) -> Result<(Vec<Data>, Vec<Issue>), Box<dyn Error>> {
+    let mut issues: Vec<Issue> = Vec::new();
+    let mut parsed: Vec<(u64, Data)> = Vec::new();
+    for row in rows {
+        match row.data {
+            Ok(data) => parsed.push((row.line, data)),
+            Err(e) => issues.push(Issue { line: row.line, txid: String::new(), problem: Problem::Malformed(e) }),
+        }
+    }
+
+    // a txid stands for its first row, later ones are duplicates
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    let mut problems: Vec<Option<Problem>> = Vec::new();
+    for (i, (_, data)) in parsed.iter().enumerate() {
+        let problem = if let Some(&first) = index.get(data.txid.as_str()) {
+            Some(Problem::Duplicate { first_line: parsed[first].0 })
+        } else if data.weight == 0 {
+            Some(Problem::ZeroWeight)
+        } else {
+            data.parents
+                .iter()
+                .find(|parent| !is_txid(parent))
+                .map(|parent| Problem::MalformedParents(parent.clone()))
+        };
+        index.entry(data.txid.as_str()).or_insert(i);
+        problems.push(problem);
+    }
+
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); parsed.len()];
+    for (i, (_, data)) in parsed.iter().enumerate() {
+        for parent in &data.parents {
+            match index.get(parent.as_str()) {
+                Some(&p) => children[p].push(i),
+                None if problems[i].is_none() => problems[i] = Some(Problem::UnknownParent(parent.clone())),
+                None => {}
+            }
+        }
+    }
+
+    // a topological walk from the parentless rows never reaches what sits on a cycle or
+    // below one, peeling the childless rows off the rest leaves the cycles
+    let mut incomings = vec![0; parsed.len()];
+    for list in &children {
+        for &child in list {
+            incomings[child] += 1;
+        }
+    }
+    let mut on_cycle = vec![true; parsed.len()];
+    let mut stack: Vec<usize> = (0..parsed.len()).filter(|&i| incomings[i] == 0).collect();
+    while let Some(i) = stack.pop() {
+        on_cycle[i] = false;
+        for &child in &children[i] {
+            incomings[child] -= 1;
+            if incomings[child] == 0 {
+                stack.push(child);
+            }
+        }
+    }
+    let mut outgoings: Vec<usize> = (0..parsed.len())
+        .map(|i| children[i].iter().filter(|&&c| on_cycle[c]).count())
+        .collect();
+    let mut stack: Vec<usize> = (0..parsed.len()).filter(|&i| on_cycle[i] && outgoings[i] == 0).collect();
+    while let Some(i) = stack.pop() {
+        on_cycle[i] = false;
+        for parent in &parsed[i].1.parents {
+            if let Some(&p) = index.get(parent.as_str()) {
+                if on_cycle[p] {
+                    outgoings[p] -= 1;
+                    if outgoings[p] == 0 {
+                        stack.push(p);
+                    }
+                }
+            }
+        }
+    }
+    for i in 0..parsed.len() {
+        if on_cycle[i] && problems[i].is_none() {
+            problems[i] = Some(Problem::Cycle);
+        }
+    }
+
+    // every row that depends on a dropped one goes as well
+    if validation == Validation::Lenient {
+        let mut stack: Vec<usize> = (0..parsed.len()).filter(|&i| problems[i].is_some()).collect();
+        while let Some(i) = stack.pop() {
+            for &child in &children[i] {
+                if problems[child].is_none() {
+                    problems[child] = Some(Problem::DroppedParent(parsed[i].1.txid.clone()));
+                    stack.push(child);
+                }
+            }
+        }
+    }
+
+    let mut kept: Vec<Data> = Vec::new();
+    for ((line, data), problem) in parsed.into_iter().zip(problems) {
+        match problem {
+            Some(problem) => issues.push(Issue { line, txid: data.txid, problem }),
+            None => kept.push(data),
+        }
+    }
+    issues.sort_by_key(|issue| issue.line);
+
+    if validation == Validation::Strict && !issues.is_empty() {
+        let lines: Vec<String> = issues.iter().map(|issue| issue.to_string()).collect();
+        return Err(format!("{} problems in the mempool\n{}", issues.len(), lines.join("\n")).into());
+    }
+    Ok((kept, issues))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::read_csv;
+
+    fn txid(c: char) -> String {
+        c.to_string().repeat(64)
+    }
+
+    // one row of each problem, and rows that only depend on them
+    fn rows() -> Vec<Row> {
+        let [a, b, c, d, e, f, one, two, three, four, nine] =
+            ['a', 'b', 'c', 'd', 'e', 'f', '1', '2', '3', '4', '9'].map(txid);
+        let csv = [
+            format!("{a},100,400,"),
+            format!("{b},100,400,{a}"),
+            format!("{a},50,400,"),
+            format!("{c},100,0,"),
+            format!("{d},100,400,{c}"),
+            format!("{e},100,400,{d}"),
+            format!("{f},100,400,{nine}"),
+            format!("{one},100,400,{two}"),
+            format!("{two},100,400,{one}"),
+            format!("{three},100,400,{two}"),
+            format!("{four},100,400,{a};zz"),
+            "x,lots,400,".to_string(),
+        ];
+        read_csv(csv.join("\n").as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_lenient_drops_rows_and_descendants() {
+        let (kept, issues) = validate(rows(), Validation::Lenient).unwrap();
+        let kept: Vec<&str> = kept.iter().map(|data| data.txid.as_str()).collect();
+        assert_eq!(kept, vec![txid('a'), txid('b')]);
+
+        let issues: Vec<(u64, String, Problem)> =
+            issues.into_iter().map(|issue| (issue.line, issue.txid, issue.problem)).collect();
+        assert_eq!(
+            issues,
+            vec![
+                (3, txid('a'), Problem::Duplicate { first_line: 1 }),
+                (4, txid('c'), Problem::ZeroWeight),
+                (5, txid('d'), Problem::DroppedParent(txid('c'))),
+                (6, txid('e'), Problem::DroppedParent(txid('d'))),
+                (7, txid('f'), Problem::UnknownParent(txid('9'))),
+                (8, txid('1'), Problem::Cycle),
+                (9, txid('2'), Problem::Cycle),
+                (10, txid('3'), Problem::DroppedParent(txid('2'))),
+                (11, txid('4'), Problem::MalformedParents("zz".to_string())),
+                (12, String::new(), Problem::Malformed("fee is not a number: \"lots\"".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_fails_on_any_problem() {
+        // the rows below a dropped one aren't problems of their own when nothing is dropped
+        let error = validate(rows(), Validation::Strict).unwrap_err().to_string();
+        let lines: Vec<&str> = error.lines().collect();
+        assert_eq!(lines[0], "7 problems in the mempool");
+        let numbers: Vec<&str> = lines[1..].iter().map(|line| line.split(':').next().unwrap()).collect();
+        assert_eq!(numbers, vec!["line 3", "line 4", "line 7", "line 8", "line 9", "line 11", "line 12"]);
+        assert_eq!(lines[1], format!("line 3: {} already appeared on line 1", txid('a')));
+
+        let (kept, issues) = validate(rows().drain(..2).collect(), Validation::Strict).unwrap();
+        assert_eq!(kept.len(), 2);
+        assert!(issues.is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::limits::Limits;
+use crate::mempool::Mempool;
+
+// a rule a candidate block breaks, lines counted from 1 in the block file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    Unknown { line: u64, txid: String },
+    Repeated { line: u64, txid: String, first_line: u64 },
+    // the parent is missing from the block or comes after the child
+    ParentNotBefore { line: u64, txid: String, parent: String },
+    Overweight { weight: u64, max_weight: u64 },
+    TooManySigops { sigops: u64, max_sigops: u64 },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Unknown { line, txid } => write!(f, "line {}: {} isn't in the mempool", line, txid),
+            Violation::Repeated { line, txid, first_line } => {
+                write!(f, "line {}: {} already appeared on line {}", line, txid, first_line)
+            }
+            Violation::ParentNotBefore { line, txid, parent } => {
+                write!(f, "line {}: {} spends {}, which isn't earlier in the block", line, txid, parent)
+            }
+            Violation::Overweight { weight, max_weight } => {
+                write!(f, "transactions weigh {}, over the {} left to them", weight, max_weight)
+            }
+            Violation::TooManySigops { sigops, max_sigops } => {
+                write!(f, "transactions have {} sigops, over the {} left to them", sigops, max_sigops)
+            }
+        }
+    }
+}
+
+// what a candidate block collects and what is wrong with it, fee and weight count every
+// known transaction once even when the block is invalid
+#[derive(Clone, Debug)]
+pub struct Verification {
+    pub count: usize,
+    pub fee: u64,
+    pub weight: u64,
+    pub sigops: u64,
+    pub limits: Limits,
+    pub violations: Vec<Violation>,
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub fn packing_efficiency(&self) -> f64 {
+        self.weight as f64 / self.limits.weight() as f64
+    }
+}
+
+// checks `txids`, the lines of a block file in order, against the mempool. blank lines are
+// skipped but still counted. the minimum feerate of `limits` is the builder's choice rather
+// than a rule, it isn't checked
+pub fn verify(mempool: &Mempool, txids: &[String], limits: &Limits) -> Verification {
+    let mut verification =
+        Verification { count: 0, fee: 0, weight: 0, sigops: 0, limits: *limits, violations: Vec::new() };
+    let mut placed: HashMap<usize, u64> = HashMap::new();
+    for (number, txid) in txids.iter().enumerate() {
+        let line = number as u64 + 1;
+        if txid.trim().is_empty() {
+            continue;
+        }
+        let Some(i) = mempool.position(txid) else {
+            verification.violations.push(Violation::Unknown { line, txid: txid.clone() });
+            continue;
+        };
+        if let Some(&first_line) = placed.get(&i) {
+            verification.violations.push(Violation::Repeated { line, txid: txid.clone(), first_line });
+            continue;
+        }
+        for &p in &mempool.parents[i] {
+            if !placed.contains_key(&p) {
+                verification.violations.push(Violation::ParentNotBefore {
+                    line,
+                    txid: txid.clone(),
+                    parent: mempool.nodes[p].txid.clone(),
+                });
+            }
+        }
+        placed.insert(i, line);
+        verification.count += 1;
+        verification.fee = verification.fee.saturating_add(mempool.nodes[i].fee);
+        verification.weight += mempool.nodes[i].weight;
+        verification.sigops += mempool.nodes[i].sigops;
+    }
+
+    if verification.weight > limits.weight() {
+        verification.violations.push(Violation::Overweight { weight: verification.weight, max_weight: limits.weight() });
+    }
+    if verification.sigops > limits.sigops() {
+        verification.violations.push(Violation::TooManySigops { sigops: verification.sigops, max_sigops: limits.sigops() });
+    }
+    verification
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::Validation;
+
+    fn txid(c: char) -> String {
+        c.to_string().repeat(64)
+    }
+
+    // b spends a, c spends b
+    fn mempool() -> Mempool {
+        let (a, b, c) = (txid('a'), txid('b'), txid('c'));
+        let csv = format!("{a},100,400,,10\n{b},200,400,{a},10\n{c},300,400,{b},10\n");
+        Mempool::from_csv(csv.as_bytes(), Validation::Strict).unwrap()
+    }
+
+    fn check(txids: &[String], max_weight: u64, max_sigops: u64) -> Verification {
+        let mut limits = Limits::new(max_weight);
+        limits.reserved_weight = 0;
+        limits.max_sigops = max_sigops;
+        limits.reserved_sigops = 0;
+        verify(&mempool(), txids, &limits)
+    }
+
+    #[test]
+    fn test_valid_block_with_blank_lines() {
+        let txids = vec![txid('a'), String::new(), txid('b'), " ".to_string(), txid('c'), String::new()];
+        let verification = check(&txids, 1200, 30);
+        assert!(verification.is_valid(), "{:?}", verification.violations);
+        assert_eq!(
+            (verification.count, verification.fee, verification.weight, verification.sigops),
+            (3, 600, 1200, 30)
+        );
+    }
+
+    #[test]
+    fn test_each_violation() {
+        let (a, b, c) = (txid('a'), txid('b'), txid('c'));
+
+        // b's parent is nowhere in the block, then it only comes after its child
+        let missing = check(std::slice::from_ref(&b), 1200, 30);
+        assert_eq!(missing.violations, vec![Violation::ParentNotBefore { line: 1, txid: b.clone(), parent: a.clone() }]);
+        let late = check(&[a.clone(), c.clone(), b.clone()], 1200, 30);
+        assert_eq!(late.violations, vec![Violation::ParentNotBefore { line: 2, txid: c.clone(), parent: b.clone() }]);
+
+        let repeated = check(&[a.clone(), String::new(), a.clone()], 1200, 30);
+        assert_eq!(repeated.violations, vec![Violation::Repeated { line: 3, txid: a.clone(), first_line: 1 }]);
+        assert_eq!((repeated.count, repeated.fee), (1, 100));
+
+        let unknown = check(&[a.clone(), txid('d')], 1200, 30);
+        assert_eq!(unknown.violations, vec![Violation::Unknown { line: 2, txid: txid('d') }]);
+
+        let block = [a, b, c];
+        assert_eq!(check(&block, 1199, 30).violations, vec![Violation::Overweight { weight: 1200, max_weight: 1199 }]);
+        assert_eq!(check(&block, 1200, 29).violations, vec![Violation::TooManySigops { sigops: 30, max_sigops: 29 }]);
+    }
+}
@@ -0,0 +1,415 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::sha256_hash;
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BECH32_ALPHABET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+// checksum constants, bech32 for witness v0 and bech32m (BIP350) for v1 and up
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc830a3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl Network {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "mainnet" | "main" | "bitcoin" => Some(Network::Mainnet),
+            "testnet" | "test" => Some(Network::Testnet),
+            "signet" => Some(Network::Signet),
+            "regtest" => Some(Network::Regtest),
+            _ => None,
+        }
+    }
+
+    fn p2pkh_prefix(&self) -> u8 {
+        match self {
+            Network::Mainnet => 0x00,
+            _ => 0x6f,
+        }
+    }
+
+    fn p2sh_prefix(&self) -> u8 {
+        match self {
+            Network::Mainnet => 0x05,
+            _ => 0xc4,
+        }
+    }
+
+    fn hrp(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "bc",
+            Network::Testnet | Network::Signet => "tb",
+            Network::Regtest => "bcrt",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AddressError {
+    InvalidCharacter(char),
+    InvalidChecksum,
+    InvalidLength(usize),
+    // the address is fine but belongs to another network
+    WrongNetwork,
+    UnknownPrefix(u8),
+    InvalidWitnessProgram,
+    // bech32 used where bech32m is required or the other way around
+    WrongChecksumVariant,
+    MixedCase,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::InvalidCharacter(c) => write!(f, "invalid character {:?}", c),
+            AddressError::InvalidChecksum => write!(f, "invalid checksum"),
+            AddressError::InvalidLength(len) => write!(f, "invalid payload length {}", len),
+            AddressError::WrongNetwork => write!(f, "address belongs to another network"),
+            AddressError::UnknownPrefix(prefix) => write!(f, "unknown version byte {}", prefix),
+            AddressError::InvalidWitnessProgram => write!(f, "invalid witness program"),
+            AddressError::WrongChecksumVariant => {
+                write!(
+                    f,
+                    "bech32 checksum variant doesn't match the witness version"
+                )
+            }
+            AddressError::MixedCase => write!(f, "mixed case address"),
+        }
+    }
+}
+
+impl Error for AddressError {}
+
+// address paying to the scriptPubKey, None for scripts that have no address form
+pub fn script_to_address(script: &[u8], network: Network) -> Option<String> {
+    match script {
+        // OP_DUP OP_HASH160 <20> OP_EQUALVERIFY OP_CHECKSIG
+        [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] if hash.len() == 20 => {
+            Some(base58check_encode(network.p2pkh_prefix(), hash))
+        }
+        // OP_HASH160 <20> OP_EQUAL
+        [0xa9, 0x14, hash @ .., 0x87] if hash.len() == 20 => {
+            Some(base58check_encode(network.p2sh_prefix(), hash))
+        }
+        // OP_n <2 to 40 bytes>
+        [version, len, program @ ..]
+            if (*version == 0 || (0x51..=0x60).contains(version))
+                && *len as usize == program.len()
+                && (2..=40).contains(&program.len()) =>
+        {
+            let version = if *version == 0 { 0 } else { version - 0x50 };
+            if version == 0 && program.len() != 20 && program.len() != 32 {
+                return None;
+            }
+            Some(segwit_encode(network.hrp(), version, program))
+        }
+        _ => None,
+    }
+}
+
+// scriptPubKey the address pays to
+pub fn address_to_script(address: &str, network: Network) -> Result<Vec<u8>, AddressError> {
+    let lower = address.to_lowercase();
+    if ["bc1", "tb1", "bcrt1"].iter().any(|p| lower.starts_with(p)) {
+        let (version, program) = segwit_decode(address, network.hrp())?;
+        let mut script = vec![if version == 0 { 0 } else { version + 0x50 }];
+        script.push(program.len() as u8);
+        script.extend_from_slice(&program);
+        return Ok(script);
+    }
+
+    let payload = base58check_decode(address)?;
+    if payload.len() != 21 {
+        return Err(AddressError::InvalidLength(payload.len()));
+    }
+    let (prefix, hash) = (payload[0], &payload[1..]);
+
+    let mut script = Vec::new();
+    if prefix == network.p2pkh_prefix() {
+        script.extend_from_slice(&[0x76, 0xa9, 0x14]);
+        script.extend_from_slice(hash);
+        script.extend_from_slice(&[0x88, 0xac]);
+    } else if prefix == network.p2sh_prefix() {
+        script.extend_from_slice(&[0xa9, 0x14]);
+        script.extend_from_slice(hash);
+        script.push(0x87);
+    } else if [0x00, 0x05, 0x6f, 0xc4].contains(&prefix) {
+        return Err(AddressError::WrongNetwork);
+    } else {
+        return Err(AddressError::UnknownPrefix(prefix));
+    }
+    Ok(script)
+}
+
+pub fn base58check_encode(prefix: u8, payload: &[u8]) -> String {
+    let mut data = vec![prefix];
+    data.extend_from_slice(payload);
+    let checksum = sha256_hash(&sha256_hash(&data));
+    data.extend_from_slice(&checksum[..4]);
+
+    // repeated division of the big endian number by 58, digits come out least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in &data {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    // every leading zero byte is written as a '1'
+    let zeros = data.iter().take_while(|b| **b == 0).count();
+    let mut encoded = "1".repeat(zeros);
+    encoded.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|d| BASE58_ALPHABET[*d as usize] as char),
+    );
+    encoded
+}
+
+// the version byte followed by the payload, with the checksum checked and stripped
+pub fn base58check_decode(s: &str) -> Result<Vec<u8>, AddressError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in s.chars() {
+        let mut carry = BASE58_ALPHABET
+            .iter()
+            .position(|a| *a as char == c)
+            .ok_or(AddressError::InvalidCharacter(c))? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let zeros = s.chars().take_while(|c| *c == '1').count();
+    let mut data = vec![0u8; zeros];
+    data.extend(bytes.iter().rev());
+
+    if data.len() < 5 {
+        return Err(AddressError::InvalidLength(data.len()));
+    }
+    let (payload, checksum) = data.split_at(data.len() - 4);
+    if sha256_hash(&sha256_hash(payload))[..4] != *checksum {
+        return Err(AddressError::InvalidChecksum);
+    }
+    Ok(payload.to_vec())
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk: u32 = 1;
+    for value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, g) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|b| b & 31));
+    expanded
+}
+
+// regroups bits, e.g. bytes into the 5 bit groups bech32 is written in
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let max = (1 << to) - 1;
+    let mut out = Vec::new();
+
+    for value in data {
+        if (*value as u32) >> from != 0 {
+            return None;
+        }
+        acc = (acc << from) | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & max) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            out.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max) != 0 {
+        return None;
+    }
+    Some(out)
+}
+
+pub fn segwit_encode(hrp: &str, version: u8, program: &[u8]) -> String {
+    let constant = if version == 0 {
+        BECH32_CONST
+    } else {
+        BECH32M_CONST
+    };
+    bech32_encode(hrp, version, program, constant)
+}
+
+fn bech32_encode(hrp: &str, version: u8, program: &[u8], constant: u32) -> String {
+    let mut data = vec![version];
+    data.extend(convert_bits(program, 8, 5, true).unwrap());
+
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    values.extend_from_slice(&[0; 6]);
+    let polymod = bech32_polymod(&values) ^ constant;
+    data.extend((0..6).map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8));
+
+    let mut encoded = format!("{}1", hrp);
+    encoded.extend(data.iter().map(|d| BECH32_ALPHABET[*d as usize] as char));
+    encoded
+}
+
+// witness version and program of a segwit address with the given human readable part
+pub fn segwit_decode(address: &str, hrp: &str) -> Result<(u8, Vec<u8>), AddressError> {
+    if address.to_lowercase() != address && address.to_uppercase() != address {
+        return Err(AddressError::MixedCase);
+    }
+    let address = address.to_lowercase();
+
+    let separator = address.rfind('1').ok_or(AddressError::InvalidChecksum)?;
+    if &address[..separator] != hrp {
+        return Err(AddressError::WrongNetwork);
+    }
+
+    let mut data = Vec::new();
+    for c in address[separator + 1..].chars() {
+        let value = BECH32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c)
+            .ok_or(AddressError::InvalidCharacter(c))?;
+        data.push(value as u8);
+    }
+    if data.len() < 7 {
+        return Err(AddressError::InvalidLength(data.len()));
+    }
+
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    let constant = match bech32_polymod(&values) {
+        BECH32_CONST => BECH32_CONST,
+        BECH32M_CONST => BECH32M_CONST,
+        _ => return Err(AddressError::InvalidChecksum),
+    };
+
+    let version = data[0];
+    let program = convert_bits(&data[1..data.len() - 6], 5, 8, false)
+        .ok_or(AddressError::InvalidWitnessProgram)?;
+
+    if version > 16 || !(2..=40).contains(&program.len()) {
+        return Err(AddressError::InvalidWitnessProgram);
+    }
+    if version == 0 && program.len() != 20 && program.len() != 32 {
+        return Err(AddressError::InvalidWitnessProgram);
+    }
+    if (version == 0) != (constant == BECH32_CONST) {
+        return Err(AddressError::WrongChecksumVariant);
+    }
+    Ok((version, program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_roundtrip() {
+        // BIP173 / BIP350 vectors plus well known base58 addresses
+        let cases = [
+            (
+                "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+                Network::Mainnet,
+            ),
+            (
+                "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                Network::Testnet,
+            ),
+            (
+                "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+                "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr",
+                Network::Mainnet,
+            ),
+            (
+                "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac",
+                "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+                Network::Mainnet,
+            ),
+            (
+                "a914748284390f9e263a4b766a75d0633c50426eb87587",
+                "3CK4fEwbMP7heJarmU4eqA3sMbVJyEnU3V",
+                Network::Mainnet,
+            ),
+        ];
+
+        for (script_hex, address, network) in cases {
+            let script = hex::decode(script_hex).unwrap();
+            assert_eq!(
+                script_to_address(&script, network).as_deref(),
+                Some(address)
+            );
+            assert_eq!(address_to_script(address, network).unwrap(), script);
+        }
+    }
+
+    #[test]
+    fn test_address_errors() {
+        // bech32 checksum on a v1 program
+        let program = [0x11; 32];
+        assert_eq!(
+            address_to_script(
+                &bech32_encode("bc", 1, &program, BECH32_CONST),
+                Network::Mainnet
+            ),
+            Err(AddressError::WrongChecksumVariant)
+        );
+        assert_eq!(
+            address_to_script(&segwit_encode("tb", 1, &program), Network::Mainnet),
+            Err(AddressError::WrongNetwork)
+        );
+        assert_eq!(
+            address_to_script("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb", Network::Mainnet),
+            Err(AddressError::InvalidChecksum)
+        );
+        assert_eq!(
+            address_to_script("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", Network::Testnet),
+            Err(AddressError::WrongNetwork)
+        );
+        // OP_RETURN has no address
+        assert_eq!(
+            script_to_address(&[0x6a, 0x01, 0x00], Network::Mainnet),
+            None
+        );
+    }
+}
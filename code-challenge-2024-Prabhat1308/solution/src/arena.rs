@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::ops::Index;
+
+use crate::rawtx::serialize_transaction;
+use crate::script::{classify, ScriptType};
+use crate::{check_input_output, collect_txids, get_wtxid, test_weight, Transaction};
+
+// position of a transaction in the arena
+pub(crate) type TxIndex = u32;
+
+// what is kept of a mempool transaction once its JSON has been looked at
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TxRecord {
+    pub(crate) txid: String,
+    // display order, like the txid
+    pub(crate) wtxid: [u8; 32],
+    pub(crate) fee: u64,
+    pub(crate) weight: u64,
+    // serialized size with witness data
+    pub(crate) size: u64,
+    pub(crate) has_witness: bool,
+    // the mempool transactions whose outputs it spends
+    pub(crate) parents: Vec<TxIndex>,
+    // scriptPubKeys of what it spends and what it creates, for the report
+    pub(crate) input_types: Vec<ScriptType>,
+    pub(crate) output_types: Vec<ScriptType>,
+}
+
+impl TxRecord {
+    fn new(tx: &Transaction) -> TxRecord {
+        let script_type = |script: &str| classify(&hex::decode(script).unwrap_or_default());
+        TxRecord {
+            txid: collect_txids(tx),
+            wtxid: get_wtxid(tx).try_into().unwrap(),
+            fee: check_input_output(tx).1,
+            weight: test_weight(tx),
+            size: serialize_transaction(tx, true).len() as u64,
+            has_witness: tx.vin.iter().any(|ins| ins.witness.is_some()),
+            parents: Vec::new(),
+            input_types: tx
+                .vin
+                .iter()
+                .map(|ins| script_type(&ins.prevout.scriptpubkey))
+                .collect(),
+            output_types: tx
+                .vout
+                .iter()
+                .map(|outs| script_type(&outs.scriptpubkey))
+                .collect(),
+        }
+    }
+}
+
+// every transaction of the mempool, stored once as a record; nodes, graphs and the report
+// refer to them by index. the transactions themselves are dropped as soon as their record
+// is made
+#[derive(Default)]
+pub(crate) struct TxArena {
+    records: Vec<TxRecord>,
+}
+
+// the transactions of a mempool directory parsed one at a time straight from disk, in file
+// name order so they come out the same on every filesystem
+pub(crate) fn stream_dir(
+    dir: &str,
+) -> Result<impl Iterator<Item = Result<Transaction, Box<dyn Error>>>, Box<dyn Error>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+
+    Ok(paths.into_iter().map(|path| {
+        let reader = BufReader::new(File::open(&path)?);
+        let tx: Transaction =
+            serde_json::from_reader(reader).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(tx)
+    }))
+}
+
+impl TxArena {
+    pub(crate) fn read_dir<F>(dir: &str, each: F) -> Result<TxArena, Box<dyn Error>>
+    where
+        F: FnMut(&TxRecord, &Transaction),
+    {
+        TxArena::build(stream_dir(dir)?, each)
+    }
+
+    // records of `txs` in order, `each` sees every transaction next to its record before the
+    // transaction goes. parents are linked once all of them are in, a child may come first
+    pub(crate) fn build<I, F>(txs: I, mut each: F) -> Result<TxArena, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>>,
+        F: FnMut(&TxRecord, &Transaction),
+    {
+        let mut arena = TxArena::default();
+        // the txids each transaction spends, only until the parents are linked
+        let mut spent: Vec<Vec<String>> = Vec::new();
+        for tx in txs {
+            let tx = tx?;
+            let record = TxRecord::new(&tx);
+            each(&record, &tx);
+            spent.push(tx.vin.into_iter().map(|ins| ins.txid).collect());
+            arena.records.push(record);
+        }
+
+        let positions: HashMap<String, TxIndex> = arena
+            .iter()
+            .map(|(i, record)| (record.txid.clone(), i))
+            .collect();
+        for (record, spent) in arena.records.iter_mut().zip(spent) {
+            for txid in spent {
+                if let Some(&p) = positions.get(&txid) {
+                    if !record.parents.contains(&p) {
+                        record.parents.push(p);
+                    }
+                }
+            }
+        }
+        Ok(arena)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (TxIndex, &TxRecord)> {
+        self.records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| (i as TxIndex, record))
+    }
+
+    // where each txid sits, built on demand so the arena doesn't hold every txid twice
+    pub(crate) fn positions(&self) -> HashMap<&str, TxIndex> {
+        self.iter()
+            .map(|(i, record)| (record.txid.as_str(), i))
+            .collect()
+    }
+}
+
+impl Index<TxIndex> for TxArena {
+    type Output = TxRecord;
+
+    fn index(&self, index: TxIndex) -> &TxRecord {
+        &self.records[index as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arena_indexing() {
+        let tx: Transaction = stream_dir("../mempool_test")
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+
+        // the child spends the parent and comes first, the parent twice over
+        let mut parent = tx.clone();
+        parent.vin[0].txid = "11".repeat(32);
+        let parent_txid = collect_txids(&parent);
+        let mut child = tx.clone();
+        child.vin[0].txid = parent_txid.clone();
+        child.vin.push(child.vin[0].clone());
+        child.vin[1].vout += 1;
+
+        let mut seen = Vec::new();
+        let arena = TxArena::build(
+            vec![Ok(child.clone()), Ok(parent.clone()), Ok(tx.clone())],
+            |record, tx| seen.push((record.txid.clone(), tx.vin.len())),
+        )
+        .unwrap();
+
+        assert_eq!(arena.len(), 3);
+        assert_eq!(arena[1].txid, parent_txid);
+        assert_eq!(arena[0].parents, vec![1]);
+        assert!(arena[1].parents.is_empty() && arena[2].parents.is_empty());
+        assert_eq!(arena.positions()[parent_txid.as_str()], 1);
+        assert_eq!(seen[0], (arena[0].txid.clone(), 2));
+
+        let record = &arena[2];
+        assert_eq!(record.txid, collect_txids(&tx));
+        assert_eq!(record.wtxid.to_vec(), get_wtxid(&tx));
+        assert_eq!(record.weight, test_weight(&tx));
+        assert_eq!(record.fee, check_input_output(&tx).1);
+        assert!(record.has_witness);
+        assert_eq!(record.input_types, vec![ScriptType::P2wpkh]);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::address::Network;
+use crate::config::{load_denylist, Clock, Config, OutputFormat, SelectionAlgorithm};
+
+pub const USAGE: &str = "usage: solution [COMMAND] [OPTIONS]
+
+commands:
+  mine                 validate, select, mine the header and write the block (default)
+  template             validate and select, write the unmined block template
+  validate             validate every transaction and print the verdicts
+  inspect-tx <FILE>    decode and explain a single transaction
+  prove <TXID>...      merkle proofs of inclusion for txids of the mined block
+  check-block          check proof of work, coinbase and merkle root of the mined block
+  compact              BIP152 compact block of the mined block
+  reconstruct <FILE>   rebuild a block from a compact block and the mempool
+  stratum-job          validate and select, write the template as a Stratum v1 job
+  submit-share <JOB_FILE> <EXTRANONCE2> <NTIME> <NONCE>
+                       check a share (hex fields as in mining.submit) against a job
+
+options:
+  --mempool <DIR>      mempool directory (default ./mempool)
+  --utxos <FILE>       UTXO snapshot, one txid:vout per line
+  --height <N>         height of the block being built (default 69)
+  --max-weight <N>     block weight limit (default 4000000)
+  --algorithm <NAME>   greedy | ancestor (default greedy)
+  --format <FORMAT>    text | json (default text)
+  --output <FILE>      output file, `-` for stdout (default ./output.txt for mine, stdout otherwise)
+  --report <FILE>      where mine writes its mempool report, in the --format, `-` for stdout
+                       (default ./report.txt or ./report.json, none when --output is `-`)
+  --no-report          mine writes no mempool report
+  --block <FILE>       mined block read by prove, check-block and compact (default ./output.txt)
+  --time <UNIX>        block time and locktime cut off instead of the system clock,
+                       makes runs over the same mempool give the same block
+  --sig-cache <FILE>   skip signature checks of transactions verified in an earlier run
+                       and add newly verified ones to FILE
+  --network <NAME>     mainnet | testnet | signet | regtest, for address checks (default mainnet)
+  --share-target <HEX> target a share has to meet (default the block target)
+  --witness-reserved <HEX>
+                       32 byte witness reserved value of the coinbase (default all zero)
+  --deny <TXID>        leave this txid out of the block, may be repeated
+  --deny-file <FILE>   leave the txids listed in FILE out of the block
+                       (--deny and --deny-file replace the built in deny-list)";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Mine,
+    Template,
+    Validate,
+    InspectTx(String),
+    Prove(Vec<String>),
+    CheckBlock,
+    Compact,
+    Reconstruct(String),
+    StratumJob,
+    SubmitShare {
+        job_file: String,
+        extranonce2: String,
+        ntime: String,
+        nonce: String,
+    },
+}
+
+pub fn parse_args(args: &[String]) -> Result<(Command, Config), String> {
+    let mut config = Config::default();
+    let mut command = None;
+    let mut output_set = false;
+    let mut report_set = false;
+    let mut denylist_set = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "prove" if command.is_none() => command = Some(Command::Prove(Vec::new())),
+            "submit-share" if command.is_none() => {
+                let mut field = |name: &str| {
+                    args.next()
+                        .map(|v| v.to_string())
+                        .ok_or(format!("submit-share needs {}", name))
+                };
+                command = Some(Command::SubmitShare {
+                    job_file: field("a job file")?,
+                    extranonce2: field("an extranonce2")?,
+                    ntime: field("an ntime")?,
+                    nonce: field("a nonce")?,
+                });
+            }
+            "mine" | "template" | "validate" | "inspect-tx" | "check-block" | "compact"
+            | "reconstruct" | "stratum-job"
+                if command.is_none() =>
+            {
+                command = Some(match arg.as_str() {
+                    "mine" => Command::Mine,
+                    "template" => Command::Template,
+                    "validate" => Command::Validate,
+                    "check-block" => Command::CheckBlock,
+                    "compact" => Command::Compact,
+                    "stratum-job" => Command::StratumJob,
+                    "reconstruct" => Command::Reconstruct(
+                        args.next().ok_or("reconstruct needs a file")?.to_string(),
+                    ),
+                    _ => Command::InspectTx(
+                        args.next().ok_or("inspect-tx needs a file")?.to_string(),
+                    ),
+                });
+            }
+            "--share-target" => {
+                let target = value(&mut args, arg)?;
+                if target.len() != 64 || hex::decode(&target).is_err() {
+                    return Err(format!("share target is not 32 bytes of hex: {}", target));
+                }
+                config.share_target = Some(target);
+            }
+            "--witness-reserved" => {
+                let reserved = value(&mut args, arg)?;
+                config.witness_reserved_value = hex::decode(&reserved)
+                    .ok()
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or(format!(
+                        "witness reserved value is not 32 bytes of hex: {}",
+                        reserved
+                    ))?;
+            }
+            "--block" => config.block_file = value(&mut args, arg)?,
+            "--mempool" => config.mempool_dir = value(&mut args, arg)?,
+            "--utxos" => config.utxo_snapshot = Some(value(&mut args, arg)?),
+            "--sig-cache" => config.sig_cache = Some(value(&mut args, arg)?),
+            "--height" => config.block_height = number(&mut args, arg)?,
+            "--max-weight" => config.max_weight = number(&mut args, arg)?,
+            "--time" => config.clock = Clock::Fixed(number(&mut args, arg)?),
+            "--algorithm" => {
+                let name = value(&mut args, arg)?;
+                config.algorithm = SelectionAlgorithm::parse(&name)
+                    .ok_or(format!("unknown algorithm: {}", name))?;
+            }
+            "--format" => {
+                let name = value(&mut args, arg)?;
+                config.output_format =
+                    OutputFormat::parse(&name).ok_or(format!("unknown format: {}", name))?;
+            }
+            "--network" => {
+                let name = value(&mut args, arg)?;
+                config.network =
+                    Network::parse(&name).ok_or(format!("unknown network: {}", name))?;
+            }
+            "--output" => {
+                let path = value(&mut args, arg)?;
+                config.output = if path == "-" { None } else { Some(path) };
+                output_set = true;
+            }
+            "--report" => {
+                config.report = Some(value(&mut args, arg)?);
+                report_set = true;
+            }
+            "--no-report" => {
+                config.report = None;
+                report_set = true;
+            }
+            "--deny" | "--deny-file" => {
+                if !denylist_set {
+                    config.denylist.clear();
+                    denylist_set = true;
+                }
+                let v = value(&mut args, arg)?;
+                if arg == "--deny" {
+                    config.denylist.insert(v);
+                } else {
+                    let txids = load_denylist(&v).map_err(|e| format!("{}: {}", v, e))?;
+                    config.denylist.extend(txids);
+                }
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => match &mut command {
+                Some(Command::Prove(txids)) if !arg.starts_with('-') => txids.push(arg.clone()),
+                _ => return Err(format!("unexpected argument: {}\n\n{}", arg, USAGE)),
+            },
+        }
+    }
+
+    let command = command.unwrap_or(Command::Mine);
+    if command == Command::Prove(Vec::new()) {
+        return Err("prove needs at least one txid".to_string());
+    }
+    if command != Command::Mine && !output_set {
+        config.output = None;
+    }
+    // the report goes next to the block unless asked for, and nowhere when the block goes to stdout
+    if !report_set {
+        config.report = match (&config.output, config.output_format) {
+            (None, _) => None,
+            (Some(_), OutputFormat::Text) => Some("./report.txt".to_string()),
+            (Some(_), OutputFormat::Json) => Some("./report.json".to_string()),
+        };
+    }
+
+    Ok((command, config))
+}
+
+fn value<'a>(args: &mut impl Iterator<Item = &'a String>, flag: &str) -> Result<String, String> {
+    args.next()
+        .map(|v| v.to_string())
+        .ok_or(format!("{} needs a value", flag))
+}
+
+fn number<'a, T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<T, String> {
+    let v = value(args, flag)?;
+    v.parse()
+        .map_err(|_| format!("{} expects a number, got {}", flag, v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let (command, config) = parse_args(&[]).unwrap();
+        assert_eq!(command, Command::Mine);
+        assert_eq!(config.clock, Clock::System);
+        assert_eq!(config.output.as_deref(), Some("./output.txt"));
+        assert_eq!(config.report.as_deref(), Some("./report.txt"));
+        assert_eq!(config.denylist.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let report = |s: &str| parse_args(&args(s)).unwrap().1.report;
+        assert_eq!(report("--format json").as_deref(), Some("./report.json"));
+        assert_eq!(report("--output -"), None);
+        assert_eq!(report("--output - --report -").as_deref(), Some("-"));
+        assert_eq!(
+            report("--report r.json --format json").as_deref(),
+            Some("r.json")
+        );
+        assert_eq!(report("--no-report"), None);
+        assert_eq!(report("template"), None);
+    }
+
+    #[test]
+    fn test_parse_template_flags() {
+        let (command, config) = parse_args(&args(
+            "template --time 1700000000 --sig-cache sigs.txt --mempool ../mempool --height 840000 --max-weight 3996000 --algorithm ancestor --format json --deny aa --deny bb",
+        ))
+        .unwrap();
+        assert_eq!(command, Command::Template);
+        assert_eq!(config.mempool_dir, "../mempool");
+        assert_eq!(config.block_height, 840000);
+        assert_eq!(config.max_weight, 3996000);
+        assert_eq!(config.clock, Clock::Fixed(1700000000));
+        assert_eq!(config.sig_cache.as_deref(), Some("sigs.txt"));
+        assert_eq!(config.algorithm, SelectionAlgorithm::AncestorFeerate);
+        assert_eq!(config.output_format, OutputFormat::Json);
+        assert_eq!(config.output, None);
+        assert!(config.denylist.contains("aa") && config.denylist.contains("bb"));
+        assert_eq!(config.denylist.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_prove() {
+        let (command, config) = parse_args(&args("prove aa --block block.txt bb")).unwrap();
+        assert_eq!(
+            command,
+            Command::Prove(vec!["aa".to_string(), "bb".to_string()])
+        );
+        assert_eq!(config.block_file, "block.txt");
+        assert_eq!(config.output, None);
+    }
+
+    #[test]
+    fn test_parse_submit_share() {
+        let target = format!("00000fff{}", "f".repeat(56));
+        let (command, config) = parse_args(&args(&format!(
+            "submit-share job.txt 00000001 6553f100 0000002a --share-target {}",
+            target
+        )))
+        .unwrap();
+        assert_eq!(
+            command,
+            Command::SubmitShare {
+                job_file: "job.txt".to_string(),
+                extranonce2: "00000001".to_string(),
+                ntime: "6553f100".to_string(),
+                nonce: "0000002a".to_string(),
+            }
+        );
+        assert_eq!(config.share_target, Some(target));
+        assert!(parse_args(&args("submit-share job.txt 00000001")).is_err());
+        assert!(parse_args(&args("stratum-job --share-target ff")).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_args(&args("--height abc")).is_err());
+        assert!(parse_args(&args("--algorithm magic")).is_err());
+        assert!(parse_args(&args("--network moon")).is_err());
+        assert!(parse_args(&args("inspect-tx")).is_err());
+        assert!(parse_args(&args("--bogus")).is_err());
+        assert!(parse_args(&args("prove")).is_err());
+        assert!(parse_args(&args("--witness-reserved 00ff")).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use crate::rawtx::{read_transaction, serialize_transaction, Reader};
+use crate::{get_wtxid, sha256_hash, turn_to_varint, Transaction};
+
+// BIP152 compact blocks, version 2: short ids are taken over wtxids
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13);
+    v[1] ^= v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16);
+    v[3] ^= v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21);
+    v[3] ^= v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17);
+    v[1] ^= v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+pub fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let m = u64::from_le_bytes(chunk.try_into().unwrap());
+        v[3] ^= m;
+        sip_round(&mut v);
+        sip_round(&mut v);
+        v[0] ^= m;
+    }
+
+    // the last block holds the leftover bytes and the length in its top byte
+    let mut last = (data.len() as u64) << 56;
+    for (i, byte) in chunks.remainder().iter().enumerate() {
+        last |= (*byte as u64) << (8 * i);
+    }
+    v[3] ^= last;
+    sip_round(&mut v);
+    sip_round(&mut v);
+    v[0] ^= last;
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrefilledTransaction {
+    // absolute position in the block, it is only differentially encoded on the wire
+    pub index: u32,
+    pub raw: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactBlock {
+    pub header: Vec<u8>,
+    pub nonce: u64,
+    // 6 byte short ids, kept in the low bits
+    pub short_ids: Vec<u64>,
+    pub prefilled: Vec<PrefilledTransaction>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reconstruction {
+    // raw transactions of the block in order, None where the mempool had no match
+    pub txs: Vec<Option<Vec<u8>>>,
+    // block positions that would have to be asked for with getblocktxn
+    pub missing: Vec<u32>,
+}
+
+impl CompactBlock {
+    // only the coinbase is prefilled, every other transaction goes by its short id
+    pub(crate) fn build(
+        header: &[u8],
+        nonce: u64,
+        coinbase: &[u8],
+        wtxids: &[[u8; 32]],
+    ) -> CompactBlock {
+        let mut block = CompactBlock {
+            header: header.to_vec(),
+            nonce,
+            short_ids: Vec::new(),
+            prefilled: vec![PrefilledTransaction {
+                index: 0,
+                raw: coinbase.to_vec(),
+            }],
+        };
+        block.short_ids = wtxids
+            .iter()
+            .map(|wtxid| block.short_id_of(wtxid))
+            .collect();
+        block
+    }
+
+    // siphash keys are the first two little endian words of sha256(header || nonce)
+    fn short_id_keys(&self) -> (u64, u64) {
+        let mut preimage = self.header.clone();
+        preimage.extend_from_slice(&self.nonce.to_le_bytes());
+        let key = sha256_hash(&preimage);
+        (
+            u64::from_le_bytes(key[0..8].try_into().unwrap()),
+            u64::from_le_bytes(key[8..16].try_into().unwrap()),
+        )
+    }
+
+    // `wtxid` in internal byte order
+    pub fn short_id(&self, wtxid: &[u8]) -> u64 {
+        let (k0, k1) = self.short_id_keys();
+        siphash24(k0, k1, wtxid) & 0xffff_ffff_ffff
+    }
+
+    // `wtxid` in display order
+    fn short_id_of(&self, wtxid: &[u8]) -> u64 {
+        let mut wtxid = wtxid.to_vec();
+        wtxid.reverse();
+        self.short_id(&wtxid)
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut raw = self.header.clone();
+        raw.extend_from_slice(&self.nonce.to_le_bytes());
+
+        raw.extend_from_slice(&turn_to_varint(self.short_ids.len() as u64));
+        for short_id in &self.short_ids {
+            raw.extend_from_slice(&short_id.to_le_bytes()[..6]);
+        }
+
+        raw.extend_from_slice(&turn_to_varint(self.prefilled.len() as u64));
+        let mut next_index = 0;
+        for prefilled in &self.prefilled {
+            raw.extend_from_slice(&turn_to_varint((prefilled.index - next_index) as u64));
+            raw.extend_from_slice(&prefilled.raw);
+            next_index = prefilled.index + 1;
+        }
+        raw
+    }
+
+    pub fn deserialize(raw: &[u8]) -> Result<CompactBlock, String> {
+        let mut reader = Reader::new(raw);
+        let header = reader.take(80)?.to_vec();
+        let nonce = reader.u64()?;
+
+        let count = reader.varint()?;
+        let mut short_ids = Vec::new();
+        for _ in 0..count {
+            let mut bytes = [0u8; 8];
+            bytes[..6].copy_from_slice(reader.take(6)?);
+            short_ids.push(u64::from_le_bytes(bytes));
+        }
+
+        let count = reader.varint()?;
+        let mut prefilled = Vec::new();
+        let mut next_index: u64 = 0;
+        for _ in 0..count {
+            let index = next_index + reader.varint()?;
+            if index > u32::MAX as u64 {
+                return Err("prefilled transaction index overflows".to_string());
+            }
+            let start = reader.pos;
+            read_transaction(&mut reader)?;
+            prefilled.push(PrefilledTransaction {
+                index: index as u32,
+                raw: raw[start..reader.pos].to_vec(),
+            });
+            next_index = index + 1;
+        }
+
+        if reader.pos != raw.len() {
+            return Err(format!(
+                "{} trailing bytes after the compact block",
+                raw.len() - reader.pos
+            ));
+        }
+        let block = CompactBlock {
+            header,
+            nonce,
+            short_ids,
+            prefilled,
+        };
+        if block
+            .prefilled
+            .last()
+            .is_some_and(|p| p.index as usize >= block.transaction_count())
+        {
+            return Err("prefilled transaction index past the end of the block".to_string());
+        }
+        Ok(block)
+    }
+
+    // fills the block from the mempool, short ids shared by two mempool transactions
+    // count as missing just like Bitcoin Core does. the mempool is gone through once and only
+    // the transactions the block asks for are kept
+    pub(crate) fn reconstruct<I>(&self, mempool: I) -> Result<Reconstruction, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = Result<Transaction, Box<dyn Error>>>,
+    {
+        let wanted: HashSet<u64> = self.short_ids.iter().copied().collect();
+        let mut by_short_id: HashMap<u64, Option<Vec<u8>>> = HashMap::new();
+        for tx in mempool {
+            let tx = tx?;
+            let short_id = self.short_id_of(&get_wtxid(&tx));
+            if !wanted.contains(&short_id) {
+                continue;
+            }
+            by_short_id
+                .entry(short_id)
+                .and_modify(|found| *found = None)
+                .or_insert_with(|| Some(serialize_transaction(&tx, true)));
+        }
+
+        let mut txs: Vec<Option<Vec<u8>>> = vec![None; self.transaction_count()];
+        for prefilled in &self.prefilled {
+            txs[prefilled.index as usize] = Some(prefilled.raw.clone());
+        }
+
+        let mut short_ids = self.short_ids.iter();
+        let mut missing = Vec::new();
+        for (index, slot) in txs.iter_mut().enumerate() {
+            if slot.is_some() {
+                continue;
+            }
+            let short_id = short_ids.next().unwrap();
+            match by_short_id.get(short_id) {
+                Some(Some(raw)) => *slot = Some(raw.clone()),
+                _ => missing.push(index as u32),
+            }
+        }
+
+        Ok(Reconstruction { txs, missing })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_siphash_vectors() {
+        // from the SipHash paper, key 00..0f
+        let k0 = u64::from_le_bytes([0, 1, 2, 3, 4, 5, 6, 7]);
+        let k1 = u64::from_le_bytes([8, 9, 10, 11, 12, 13, 14, 15]);
+        let message: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash24(k0, k1, &message), 0xa129ca6149be45e5);
+        assert_eq!(siphash24(k0, k1, &[]), 0x726fdb47dd0e0e31);
+    }
+
+    #[test]
+    fn test_compact_block_roundtrip() {
+        let mempool: Vec<Transaction> = fs::read_dir("../mempool_test")
+            .unwrap()
+            .map(|entry| {
+                serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap()
+            })
+            .collect();
+
+        let header = vec![7u8; 80];
+        let coinbase = serialize_transaction(&mempool[0], true);
+        let wtxids: Vec<[u8; 32]> = mempool
+            .iter()
+            .map(|tx| get_wtxid(tx).try_into().unwrap())
+            .collect();
+        let block = CompactBlock::build(&header, 42, &coinbase, &wtxids);
+
+        let raw = block.serialize();
+        assert_eq!(
+            raw.len(),
+            80 + 8 + 1 + 6 * mempool.len() + 1 + 1 + coinbase.len()
+        );
+        assert_eq!(CompactBlock::deserialize(&raw).unwrap(), block);
+
+        let full = block.reconstruct(mempool.iter().cloned().map(Ok)).unwrap();
+        assert!(full.missing.is_empty());
+        assert_eq!(full.txs[1], Some(serialize_transaction(&mempool[0], true)));
+
+        let empty = block.reconstruct(Vec::new()).unwrap();
+        assert_eq!(
+            empty.missing,
+            (1..=mempool.len() as u32).collect::<Vec<_>>()
+        );
+    }
+}
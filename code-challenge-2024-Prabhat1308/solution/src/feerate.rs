@@ -0,0 +1,107 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// fee per weight unit kept as the exact fraction, compared by cross multiplication so that
+// no two distinct rates collapse into the same float
+#[derive(Clone, Copy, Debug)]
+pub struct FeeRate {
+    pub fee: u64,
+    pub weight: u64,
+}
+
+impl FeeRate {
+    pub fn new(fee: u64, weight: u64) -> Self {
+        FeeRate { fee, weight }
+    }
+
+    // rate of `sat_vb` sat per virtual byte, one vbyte being 4 weight units
+    pub fn from_sat_per_vbyte(sat_vb: u64) -> Self {
+        FeeRate {
+            fee: sat_vb,
+            weight: 4,
+        }
+    }
+
+    // only for display, never for ordering
+    pub fn sat_per_vbyte(&self) -> f64 {
+        if self.weight == 0 {
+            return 0.0;
+        }
+        self.fee as f64 * 4.0 / self.weight as f64
+    }
+
+    // 0/0 is treated as a zero rate, x/0 with x > 0 as an infinite one
+    fn normalized(&self) -> FeeRate {
+        if self.weight == 0 && self.fee == 0 {
+            FeeRate { fee: 0, weight: 1 }
+        } else {
+            *self
+        }
+    }
+}
+
+impl Ord for FeeRate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let a = self.normalized();
+        let b = other.normalized();
+
+        match (a.weight == 0, b.weight == 0) {
+            (true, true) => a.fee.cmp(&b.fee),
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => {
+                let lhs = a.fee as u128 * b.weight as u128;
+                let rhs = b.fee as u128 * a.weight as u128;
+                lhs.cmp(&rhs)
+            }
+        }
+    }
+}
+
+impl PartialOrd for FeeRate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FeeRate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FeeRate {}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.2} sat/vB", self.sat_per_vbyte())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feerate_cross_multiplication() {
+        assert_eq!(FeeRate::new(1, 2), FeeRate::new(2, 4));
+        assert!(FeeRate::new(1, 3) < FeeRate::new(1, 2));
+
+        // these two collapse to the same f64
+        let a = FeeRate::new(u64::MAX, u64::MAX - 1);
+        let b = FeeRate::new(u64::MAX - 1, u64::MAX - 2);
+        assert_eq!(
+            a.fee as f64 / a.weight as f64,
+            b.fee as f64 / b.weight as f64
+        );
+        assert!(a < b);
+    }
+
+    #[test]
+    fn test_feerate_zero_weight() {
+        assert!(FeeRate::new(1, 0) > FeeRate::new(u64::MAX, 1));
+        assert!(FeeRate::new(2, 0) > FeeRate::new(1, 0));
+        assert_eq!(FeeRate::new(0, 0), FeeRate::new(0, 10));
+        assert!(FeeRate::new(0, 0) < FeeRate::new(1, 10));
+    }
+}
@@ -0,0 +1,511 @@
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+
+use crate::config::{Config, OutputFormat};
+use crate::feerate::FeeRate;
+use crate::rawtx::{decode_raw_transaction, serialize_transaction};
+use crate::script::{classify, disassemble, parse_script, Instruction, ScriptType};
+use crate::{
+    address_mismatches, asm_mismatches, check_input_output, check_input_sig, check_locktime,
+    collect_txids, emit, get_wtxid, test_weight, type_mismatches, Input, Transaction,
+};
+
+// input types check_input_sig actually verifies, everything else passes unchecked. of p2sh
+// only the nested segwit spends are, a legacy p2sh spend comes out as not valid
+const VERIFIED_TYPES: [ScriptType; 4] = [
+    ScriptType::P2pkh,
+    ScriptType::P2sh,
+    ScriptType::P2wpkh,
+    ScriptType::P2wsh,
+];
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureCheck {
+    Valid,
+    Invalid,
+    Unchecked,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct InputInspection {
+    pub outpoint: String,
+    pub script_type: String,
+    pub prevout_asm: Option<String>,
+    pub scriptsig_asm: String,
+    pub redeem_script_asm: Option<String>,
+    pub witness: Vec<String>,
+    pub witness_script_asm: Option<String>,
+    pub signature: SignatureCheck,
+    pub sighash_types: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct OutputInspection {
+    pub value: u64,
+    pub script_type: String,
+    pub asm: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TxInspection {
+    pub txid: String,
+    pub wtxid: String,
+    pub size: u64,
+    pub weight: u64,
+    pub vsize: u64,
+    // unknown for raw transactions, which don't carry the values they spend
+    pub fee: Option<u64>,
+    pub feerate_sat_vb: Option<f64>,
+    pub locktime_final: bool,
+    pub inputs: Vec<InputInspection>,
+    pub outputs: Vec<OutputInspection>,
+    // asm fields of the JSON that don't match the disassembled hex
+    pub asm_mismatches: Vec<String>,
+    pub address_mismatches: Vec<String>,
+    pub type_mismatches: Vec<String>,
+}
+
+// `source` is a file with a transaction in the mempool JSON format or as raw hex, or the raw hex itself
+pub fn inspect_tx(source: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    let contents = match fs::read_to_string(source) {
+        Ok(contents) => contents,
+        Err(_) if hex::decode(source.trim()).is_ok() => source.to_string(),
+        Err(e) => return Err(format!("{}: {}", source, e).into()),
+    };
+    let contents = contents.trim();
+
+    let (tx, prevouts_known) = if contents.starts_with('{') {
+        let tx: Transaction = serde_json::from_str(contents)?;
+        (tx, true)
+    } else {
+        (decode_raw_transaction(&hex::decode(contents)?)?, false)
+    };
+
+    let inspection = inspect(&tx, prevouts_known, config);
+
+    match config.output_format {
+        OutputFormat::Text => emit(config.output.as_deref(), render(&inspection)),
+        OutputFormat::Json => emit(
+            config.output.as_deref(),
+            vec![serde_json::to_string_pretty(&inspection)?],
+        ),
+    }
+}
+
+pub(crate) fn inspect(tx: &Transaction, prevouts_known: bool, config: &Config) -> TxInspection {
+    let weight = test_weight(tx);
+    let fee = if prevouts_known {
+        Some(check_input_output(tx).1)
+    } else {
+        None
+    };
+
+    let inputs = tx
+        .vin
+        .iter()
+        .enumerate()
+        .map(|(index, ins)| inspect_input(tx, index, ins, prevouts_known))
+        .collect();
+
+    let outputs = tx
+        .vout
+        .iter()
+        .map(|outs| OutputInspection {
+            value: outs.value,
+            script_type: classify(&hex::decode(&outs.scriptpubkey).unwrap_or_default())
+                .label()
+                .to_string(),
+            asm: disassemble(&hex::decode(&outs.scriptpubkey).unwrap_or_default()),
+        })
+        .collect();
+
+    TxInspection {
+        txid: collect_txids(tx),
+        wtxid: hex::encode(get_wtxid(tx)),
+        size: serialize_transaction(tx, true).len() as u64,
+        weight,
+        vsize: weight.div_ceil(4),
+        fee,
+        feerate_sat_vb: fee.map(|fee| FeeRate::new(fee, weight).sat_per_vbyte()),
+        locktime_final: check_locktime(tx.clone(), config.block_height, config.clock.now()),
+        inputs,
+        outputs,
+        // a decoded raw transaction has its asm generated from the hex, nothing to compare
+        asm_mismatches: if prevouts_known {
+            asm_mismatches(tx)
+        } else {
+            Vec::new()
+        },
+        address_mismatches: if prevouts_known {
+            address_mismatches(tx, config.network)
+        } else {
+            Vec::new()
+        },
+        type_mismatches: if prevouts_known {
+            type_mismatches(tx)
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+fn inspect_input(
+    tx: &Transaction,
+    index: usize,
+    ins: &Input,
+    prevouts_known: bool,
+) -> InputInspection {
+    let scriptsig = hex::decode(&ins.scriptsig).unwrap_or_default();
+    let witness: Vec<String> = ins.witness.clone().unwrap_or_default();
+    let prevout = hex::decode(&ins.prevout.scriptpubkey).unwrap_or_default();
+    // a raw transaction doesn't tell what its inputs spend
+    let script_type = if prevouts_known {
+        Some(classify(&prevout))
+    } else {
+        None
+    };
+
+    // the redeem script is the last push of a p2sh scriptSig
+    let redeem_script_asm = match parse_script(&scriptsig) {
+        Ok(instructions) if script_type == Some(ScriptType::P2sh) => match instructions.last() {
+            Some(Instruction::Push { data, .. }) => Some(disassemble(data)),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    let witness_script_asm = match script_type {
+        Some(ScriptType::P2wsh) => witness.last(),
+        Some(ScriptType::P2sh) if witness.len() > 2 => witness.last(),
+        Some(ScriptType::P2tr) => tapscript(&witness),
+        _ => None,
+    }
+    .map(|script| disassemble(&hex::decode(script).unwrap_or_default()));
+
+    let verified = match script_type {
+        Some(ScriptType::P2sh) => ins.witness.is_some(),
+        Some(script_type) => VERIFIED_TYPES.contains(&script_type),
+        None => false,
+    };
+    let signature = if !verified {
+        SignatureCheck::Unchecked
+    } else if check_input_sig(tx, index, ins) {
+        SignatureCheck::Valid
+    } else {
+        SignatureCheck::Invalid
+    };
+
+    InputInspection {
+        outpoint: format!("{}:{}", ins.txid, ins.vout),
+        prevout_asm: prevouts_known.then(|| disassemble(&prevout)),
+        script_type: script_type.map_or("unknown", |t| t.label()).to_string(),
+        scriptsig_asm: disassemble(&scriptsig),
+        redeem_script_asm,
+        witness_script_asm,
+        signature,
+        sighash_types: sighash_types(script_type, &scriptsig, &witness),
+        witness,
+    }
+}
+
+// the witness without the annex, which is the last item when it starts with 0x50
+fn without_annex(witness: &[String]) -> &[String] {
+    match witness.last() {
+        Some(last) if witness.len() > 1 && last.starts_with("50") => &witness[..witness.len() - 1],
+        _ => witness,
+    }
+}
+
+// in a script path spend the tapscript sits right before the control block
+fn tapscript(witness: &[String]) -> Option<&String> {
+    let witness = without_annex(witness);
+    if witness.len() < 2 {
+        return None;
+    }
+    witness.get(witness.len() - 2)
+}
+
+fn sighash_types(
+    script_type: Option<ScriptType>,
+    scriptsig: &[u8],
+    witness: &[String],
+) -> Vec<String> {
+    let mut types = Vec::new();
+
+    if script_type == Some(ScriptType::P2tr) {
+        // key path: a lone 64 byte signature uses SIGHASH_DEFAULT, 65 bytes carry the type
+        if let [sig] = without_annex(witness) {
+            match sig.len() / 2 {
+                64 => types.push("DEFAULT".to_string()),
+                65 => types.push(sighash_name(hex::decode(sig).unwrap()[64])),
+                _ => {}
+            }
+        }
+        return types;
+    }
+
+    let mut pushes: Vec<Vec<u8>> = witness
+        .iter()
+        .filter_map(|item| hex::decode(item).ok())
+        .collect();
+    if let Ok(instructions) = parse_script(scriptsig) {
+        for instruction in instructions {
+            if let Instruction::Push { data, .. } = instruction {
+                pushes.push(data);
+            }
+        }
+    }
+
+    for push in pushes {
+        if looks_like_ecdsa_signature(&push) {
+            types.push(sighash_name(*push.last().unwrap()));
+        }
+    }
+    types
+}
+
+// DER encoded ECDSA signature followed by the sighash byte
+fn looks_like_ecdsa_signature(push: &[u8]) -> bool {
+    push.len() >= 9 && push.len() <= 73 && push[0] == 0x30 && push[1] as usize == push.len() - 3
+}
+
+fn sighash_name(sighash: u8) -> String {
+    let base = match sighash & 0x1f {
+        0x01 => "ALL".to_string(),
+        0x02 => "NONE".to_string(),
+        0x03 => "SINGLE".to_string(),
+        _ => format!("0x{:02x}", sighash),
+    };
+    if sighash & 0x80 != 0 {
+        format!("{}|ANYONECANPAY", base)
+    } else {
+        base
+    }
+}
+
+fn render(inspection: &TxInspection) -> Vec<String> {
+    let mut lines = vec![
+        format!("txid {}", inspection.txid),
+        format!("wtxid {}", inspection.wtxid),
+        format!("size {}", inspection.size),
+        format!("weight {}", inspection.weight),
+        format!("vsize {}", inspection.vsize),
+    ];
+    match (inspection.fee, inspection.feerate_sat_vb) {
+        (Some(fee), Some(rate)) => {
+            lines.push(format!("fee {}", fee));
+            lines.push(format!("feerate {:.2} sat/vB", rate));
+        }
+        _ => lines.push("fee unknown (no prevouts in raw transactions)".to_string()),
+    }
+    lines.push(format!("locktime_final {}", inspection.locktime_final));
+
+    for (i, input) in inspection.inputs.iter().enumerate() {
+        lines.push(format!("input {} {}", i, input.outpoint));
+        lines.push(format!("  type {}", input.script_type));
+        if let Some(asm) = &input.prevout_asm {
+            lines.push(format!("  prevout {}", asm));
+        }
+        lines.push(format!("  scriptsig {}", input.scriptsig_asm));
+        if let Some(asm) = &input.redeem_script_asm {
+            lines.push(format!("  redeem_script {}", asm));
+        }
+        for item in &input.witness {
+            lines.push(format!("  witness {}", item));
+        }
+        if let Some(asm) = &input.witness_script_asm {
+            lines.push(format!("  witness_script {}", asm));
+        }
+        lines.push(format!("  signature {:?}", input.signature).to_lowercase());
+        if !input.sighash_types.is_empty() {
+            lines.push(format!("  sighash {}", input.sighash_types.join(" ")));
+        }
+    }
+
+    for (i, output) in inspection.outputs.iter().enumerate() {
+        lines.push(format!(
+            "output {} {} {} {}",
+            i, output.value, output.script_type, output.asm
+        ));
+    }
+    for field in &inspection.asm_mismatches {
+        lines.push(format!("asm_mismatch {}", field));
+    }
+    for field in &inspection.address_mismatches {
+        lines.push(format!("address_mismatch {}", field));
+    }
+    for field in &inspection.type_mismatches {
+        lines.push(format!("type_mismatch {}", field));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Network;
+
+    #[test]
+    fn test_inspect_p2wpkh() {
+        for entry in fs::read_dir("../mempool_test").unwrap() {
+            let tx: Transaction =
+                serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap();
+
+            let config = Config {
+                block_height: 840000,
+                ..Config::default()
+            };
+            let inspection = inspect(&tx, true, &config);
+            assert_eq!(inspection.txid, collect_txids(&tx));
+            assert!(inspection.asm_mismatches.is_empty());
+            assert!(inspection.address_mismatches.is_empty());
+            assert!(inspection.type_mismatches.is_empty());
+
+            let mut tampered = tx.clone();
+            tampered.vout[0].scriptpubkey_asm.push_str(" OP_DROP");
+            tampered.vin[0].prevout.scriptpubkey_type = "p2pkh".to_string();
+            assert_eq!(
+                inspect(&tampered, true, &config).type_mismatches,
+                vec!["vin[0].prevout.scriptpubkey_type".to_string()]
+            );
+            assert_eq!(
+                inspect(&tampered, true, &config).asm_mismatches,
+                vec!["vout[0].scriptpubkey_asm".to_string()]
+            );
+
+            let testnet = Config {
+                network: Network::Testnet,
+                ..config
+            };
+            assert_eq!(
+                inspect(&tx, true, &testnet).address_mismatches.len(),
+                tx.vin.len() + tx.vout.len()
+            );
+            assert_eq!(inspection.inputs.len(), tx.vin.len());
+            for (input, ins) in inspection.inputs.iter().zip(&tx.vin) {
+                assert_eq!(input.signature, SignatureCheck::Valid);
+                assert_eq!(input.sighash_types, vec!["ALL".to_string()]);
+                assert_eq!(
+                    input.prevout_asm.as_deref(),
+                    Some(ins.prevout.scriptpubkey_asm.as_str())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_inspect_legacy_p2sh_is_unchecked() {
+        for entry in fs::read_dir("../mempool_pure_p2sh").unwrap() {
+            let tx: Transaction =
+                serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap();
+
+            let inspection = inspect(&tx, true, &Config::default());
+            for input in &inspection.inputs {
+                assert_eq!(input.script_type, "p2sh");
+                assert_eq!(input.signature, SignatureCheck::Unchecked);
+                assert!(input.redeem_script_asm.is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_inspect_missing_witness_is_invalid() {
+        for entry in fs::read_dir("../mempool_test").unwrap() {
+            let tx: Transaction =
+                serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap();
+
+            let mut stripped = tx.clone();
+            stripped.vin[0].witness = None;
+            let inspection = inspect(&stripped, true, &Config::default());
+            assert_eq!(inspection.inputs[0].script_type, "v0_p2wpkh");
+            assert_eq!(inspection.inputs[0].signature, SignatureCheck::Invalid);
+
+            // a lone signature without its public key
+            let mut short = tx.clone();
+            short.vin[0].witness.as_mut().unwrap().truncate(1);
+            let inspection = inspect(&short, true, &Config::default());
+            assert_eq!(inspection.inputs[0].signature, SignatureCheck::Invalid);
+        }
+    }
+
+    // the only transaction in a fixture directory, with the index of each of its inputs of `script_type`
+    fn fixture(dir: &str, script_type: &str) -> (Transaction, Vec<usize>) {
+        let entry = fs::read_dir(dir).unwrap().next().unwrap();
+        let tx: Transaction =
+            serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap();
+        let inputs = (0..tx.vin.len())
+            .filter(|&i| tx.vin[i].prevout.scriptpubkey_type == script_type)
+            .collect::<Vec<_>>();
+        assert!(!inputs.is_empty());
+        (tx, inputs)
+    }
+
+    #[test]
+    fn test_inspect_truncated_p2pkh_scriptsig_is_invalid() {
+        let (tx, inputs) = fixture("../mempool_p2pkh", "p2pkh");
+        let inspection = inspect(&tx, true, &Config::default());
+        for &i in &inputs {
+            assert_eq!(inspection.inputs[i].signature, SignatureCheck::Valid);
+        }
+
+        // cut before the length byte, right after it, inside the signature, and just past it
+        let scriptsig = &tx.vin[inputs[0]].scriptsig;
+        for len in [
+            0,
+            2,
+            20,
+            2 + 2 * usize::from_str_radix(&scriptsig[..2], 16).unwrap(),
+        ] {
+            let mut truncated = tx.clone();
+            truncated.vin[inputs[0]].scriptsig = scriptsig[..len].to_string();
+            let inspection = inspect(&truncated, true, &Config::default());
+            assert_eq!(
+                inspection.inputs[inputs[0]].signature,
+                SignatureCheck::Invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_inspect_non_der_p2wsh_signature_is_invalid() {
+        for (dir, script_type) in [
+            ("../mempool_p2wsh", "v0_p2wsh"),
+            ("../mempool_p2sh_p2wsh", "p2sh"),
+        ] {
+            let (tx, inputs) = fixture(dir, script_type);
+            let inspection = inspect(&tx, true, &Config::default());
+            assert_eq!(
+                inspection.inputs[inputs[0]].signature,
+                SignatureCheck::Valid
+            );
+
+            // the first item is the empty one CHECKMULTISIG pops extra, the second a signature
+            let mut tampered = tx.clone();
+            tampered.vin[inputs[0]].witness.as_mut().unwrap()[1] = "deadbeef".to_string();
+            let inspection = inspect(&tampered, true, &Config::default());
+            assert_eq!(
+                inspection.inputs[inputs[0]].signature,
+                SignatureCheck::Invalid
+            );
+
+            // an empty witness script
+            let mut tampered = tx.clone();
+            let witness = tampered.vin[inputs[0]].witness.as_mut().unwrap();
+            *witness.last_mut().unwrap() = String::new();
+            let inspection = inspect(&tampered, true, &Config::default());
+            assert_eq!(
+                inspection.inputs[inputs[0]].signature,
+                SignatureCheck::Invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_sighash_name() {
+        assert_eq!(sighash_name(0x01), "ALL");
+        assert_eq!(sighash_name(0x83), "SINGLE|ANYONECANPAY");
+        assert_eq!(sighash_name(0x00), "0x00");
+    }
+}
@@ -6,15 +6,39 @@ use serde::Deserialize;
 use sha2::Digest;
 use sha2::Sha256;
 use std::cmp::Ordering;
-use std::collections::BinaryHeap;
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs;
 use std::fs::write;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 use num_bigint::BigUint;
 
+pub mod address;
+mod arena;
+pub mod cli;
+pub mod compact;
+pub mod config;
+pub mod feerate;
+pub mod inspect;
+pub mod merkle;
+mod rawtx;
+pub mod report;
+pub mod script;
+mod selection;
+mod sigcache;
+pub mod stratum;
+
+use address::{script_to_address, Network};
+use arena::{TxArena, TxIndex, TxRecord};
+use config::{load_utxo_snapshot, Config, OutputFormat, SelectionAlgorithm};
+use feerate::FeeRate;
+use rawtx::raw_txid;
+use report::{MempoolReport, RejectReason};
+use script::{classify, disassemble, pushed_data, ScriptType};
+use sigcache::SigCache;
+
+pub use inspect::inspect_tx;
+
 #[derive(Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
 struct Transaction {
     version: u32,
@@ -55,21 +79,27 @@ struct Output {
     value: u64,
 }
 
-#[derive(Eq, PartialEq, Hash, Clone)]
+#[derive(Eq, PartialEq, Clone)]
 struct TxNode {
+    // where the transaction itself sits in the mempool's arena
+    index: TxIndex,
     txid: String,
     fee: u64,
     weight: u64,
-    tx: Transaction,
+}
+
+impl TxNode {
+    fn feerate(&self) -> FeeRate {
+        FeeRate::new(self.fee, self.weight)
+    }
 }
 
 impl Ord for TxNode {
     fn cmp(&self, other: &Self) -> Ordering {
-        let self_ratio = self.fee as f64 / self.weight as f64;
-        let other_ratio = other.fee as f64 / other.weight as f64;
-        self_ratio
-            .partial_cmp(&other_ratio)
-            .unwrap_or(Ordering::Equal)
+        // on equal feerates the smaller txid wins, so the heap pops in a fixed order
+        self.feerate()
+            .cmp(&other.feerate())
+            .then_with(|| other.txid.cmp(&self.txid))
     }
 }
 
@@ -80,152 +110,947 @@ impl PartialOrd for TxNode {
 }
 
 pub fn run() {
-    let mut valid_tx_vector: Vec<Transaction> = Vec::new();
-    let block_height: u32 = 69;
-    let mut tx_to_tx_node: HashMap<Transaction, TxNode> = HashMap::new();
-    let mut valid_wtxid: Vec<Vec<u8>> = Vec::new();
+    mine(&Config::default()).unwrap();
+}
+
+pub struct BlockTemplate {
+    pub height: u32,
+    pub coinbase: String,
+    // the coinbase without its extranonce, for miners that roll their own
+    pub coinbase_parts: CoinbaseParts,
+    pub txids: Vec<String>,
+    // wtxids in display order, the coinbase's all zero one first
+    pub wtxids: Vec<Vec<u8>>,
+    pub merkle_root: [u8; 32],
+    pub fees: u64,
+    pub weight: u64,
+}
 
-    let coinbase_in = "0000000000000000000000000000000000000000000000000000000000000000";
-    let coinbase_in_decoded = hex::decode(coinbase_in).unwrap();
-    valid_wtxid.push(coinbase_in_decoded);
+pub(crate) struct Verdict {
+    pub(crate) txid: String,
+    pub(crate) rejection: Option<RejectReason>,
+    // asm fields of the JSON that don't match their script hex, these don't reject the transaction
+    pub(crate) asm_mismatches: Vec<String>,
+    // same for the scriptpubkey_address fields
+    pub(crate) address_mismatches: Vec<String>,
+    // and the scriptpubkey_type labels
+    pub(crate) type_mismatches: Vec<String>,
+}
 
-    for entry in fs::read_dir("./mempool").unwrap() {
-        let tx: Transaction =
-            serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap();
+pub(crate) struct LoadedMempool {
+    pub(crate) arena: TxArena,
+    pub(crate) valid: Vec<TxNode>,
+    // every transaction of the mempool in directory order
+    pub(crate) verdicts: Vec<Verdict>,
+    pub(crate) report: MempoolReport,
+}
+
+// checks 2 to 4 and the deny-list, whether the inputs exist is up to the caller
+fn validate_transaction(
+    tx: &Transaction,
+    txid: &str,
+    config: &Config,
+    sig_cache: &mut SigCache,
+) -> Result<(), RejectReason> {
+    //check 2 (check for if inputs > outputs)
+    let (check2, _) = check_input_output(tx);
+    if !check2 {
+        return Err(RejectReason::InsufficientInputs);
+    }
 
-        //check 1 (all are valid utxos from given check the ones while block building (timelocks specifically))
+    //check 3 (check for signatures validity )
+    if !check_sig(tx.clone(), sig_cache) {
+        return Err(RejectReason::InvalidSignature);
+    }
+
+    // check 4 (locktime check)
+    if !check_locktime(tx.clone(), config.block_height, config.clock.now()) {
+        return Err(RejectReason::NonFinalLocktime);
+    }
 
-        //check 2 (check for if inputs > outputs)
-        let (check2, fee) = check_input_output(tx.clone());
+    if config.denylist.contains(txid) {
+        return Err(RejectReason::Denylisted);
+    }
+
+    Ok(())
+}
 
-        //check 3 (check for signatures validity )
-        let check3: bool = check_sig(tx.clone());
+// names of the asm fields that disagree with the disassembly of the hex they describe
+pub(crate) fn asm_mismatches(tx: &Transaction) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    let mut compare = |name: String, script: &[u8], asm: &str| {
+        if disassemble(script) != asm {
+            mismatches.push(name);
+        }
+    };
 
-        // check 4 (locktime check)
-        let check4: bool = check_locktime(tx.clone(), block_height);
+    for (i, ins) in tx.vin.iter().enumerate() {
+        let prevout = hex::decode(&ins.prevout.scriptpubkey).unwrap_or_default();
+        compare(
+            format!("vin[{}].prevout.scriptpubkey_asm", i),
+            &prevout,
+            &ins.prevout.scriptpubkey_asm,
+        );
 
-        let weight: u64 = test_weight(tx.clone());
+        let scriptsig = hex::decode(&ins.scriptsig).unwrap_or_default();
+        compare(
+            format!("vin[{}].scriptsig_asm", i),
+            &scriptsig,
+            &ins.scriptsig_asm,
+        );
 
-        if check2 && check3 && check4 {
-            let txid_str = collect_txids(tx.clone());
-            let tx_node = TxNode {
-                txid: txid_str.clone(),
-                fee,
-                weight,
-                tx: tx.clone(),
-            };
-            if txid_str == "e942daaa7f3776f1d640ade0106b181faa9a794708ab76b2e99604f26e4ed807" {
-                continue;
+        // the redeem script is the last push of the scriptSig
+        if let Some(asm) = &ins.inner_redeemscript_asm {
+            let redeem_script = pushed_data(&scriptsig).pop().unwrap_or_default();
+            compare(
+                format!("vin[{}].inner_redeemscript_asm", i),
+                &redeem_script,
+                asm,
+            );
+        }
+
+        // and the witness script the last witness item
+        if let Some(asm) = &ins.inner_witnessscript_asm {
+            let witness_script = ins
+                .witness
+                .as_ref()
+                .and_then(|witness| witness.last())
+                .and_then(|item| hex::decode(item).ok())
+                .unwrap_or_default();
+            compare(
+                format!("vin[{}].inner_witnessscript_asm", i),
+                &witness_script,
+                asm,
+            );
+        }
+    }
+
+    for (i, outs) in tx.vout.iter().enumerate() {
+        let scriptpubkey = hex::decode(&outs.scriptpubkey).unwrap_or_default();
+        compare(
+            format!("vout[{}].scriptpubkey_asm", i),
+            &scriptpubkey,
+            &outs.scriptpubkey_asm,
+        );
+    }
+
+    mismatches
+}
+
+// names of the scriptpubkey_type labels that don't fit the classified scriptPubKey
+pub(crate) fn type_mismatches(tx: &Transaction) -> Vec<String> {
+    let prevouts = tx.vin.iter().enumerate().map(|(i, ins)| {
+        (
+            format!("vin[{}].prevout.scriptpubkey_type", i),
+            &ins.prevout.scriptpubkey,
+            &ins.prevout.scriptpubkey_type,
+        )
+    });
+    let outputs = tx.vout.iter().enumerate().map(|(i, outs)| {
+        (
+            format!("vout[{}].scriptpubkey_type", i),
+            &outs.scriptpubkey,
+            &outs.scriptpubkey_type,
+        )
+    });
+
+    prevouts
+        .chain(outputs)
+        .filter(|(_, script, label)| {
+            let script = hex::decode(script).unwrap_or_default();
+            !classify(&script).matches_label(label)
+        })
+        .map(|(name, _, _)| name)
+        .collect()
+}
+
+// names of the scriptpubkey_address fields that aren't the address of their scriptPubKey
+pub(crate) fn address_mismatches(tx: &Transaction, network: Network) -> Vec<String> {
+    let prevouts = tx.vin.iter().enumerate().map(|(i, ins)| {
+        (
+            format!("vin[{}].prevout.scriptpubkey_address", i),
+            &ins.prevout.scriptpubkey,
+            &ins.prevout.scriptpubkey_address,
+        )
+    });
+    let outputs = tx.vout.iter().enumerate().map(|(i, outs)| {
+        (
+            format!("vout[{}].scriptpubkey_address", i),
+            &outs.scriptpubkey,
+            &outs.scriptpubkey_address,
+        )
+    });
+
+    prevouts
+        .chain(outputs)
+        .filter(|(_, script, address)| {
+            let script = hex::decode(script).unwrap_or_default();
+            script_to_address(&script, network) != **address
+        })
+        .map(|(name, _, _)| name)
+        .collect()
+}
+
+pub(crate) fn load_mempool(config: &Config) -> Result<LoadedMempool, Box<dyn Error>> {
+    let utxos = match &config.utxo_snapshot {
+        Some(path) => Some(load_utxo_snapshot(path)?),
+        None => None,
+    };
+
+    let mut sig_cache = SigCache::load(config.sig_cache.as_deref())?;
+    let mut report = MempoolReport::new();
+    let mut verdicts = Vec::new();
+    // txids the inputs spend that aren't in the utxo snapshot, they have to be in the mempool
+    let mut outside_snapshot: Vec<Vec<String>> = Vec::new();
+
+    // the transactions themselves are only around while their record is made
+    let arena = TxArena::read_dir(&config.mempool_dir, |record, tx| {
+        report.transactions_seen += 1;
+
+        let asm_mismatches = asm_mismatches(tx);
+        if !asm_mismatches.is_empty() {
+            report.asm_mismatches += 1;
+        }
+        let address_mismatches = address_mismatches(tx, config.network);
+        if !address_mismatches.is_empty() {
+            report.address_mismatches += 1;
+        }
+        let type_mismatches = type_mismatches(tx);
+        if !type_mismatches.is_empty() {
+            report.type_mismatches += 1;
+        }
+
+        if let Some(utxos) = &utxos {
+            outside_snapshot.push(
+                tx.vin
+                    .iter()
+                    .filter(|ins| !utxos.contains(&(ins.txid.clone(), ins.vout)))
+                    .map(|ins| ins.txid.clone())
+                    .collect(),
+            );
+        }
+
+        verdicts.push(Verdict {
+            txid: record.txid.clone(),
+            rejection: validate_transaction(tx, &record.txid, config, &mut sig_cache).err(),
+            asm_mismatches,
+            address_mismatches,
+            type_mismatches,
+        });
+    })?;
+
+    //check 1 (every input spends a utxo from the snapshot or an output of another mempool transaction)
+    let positions = arena.positions();
+    for (verdict, txids) in verdicts.iter_mut().zip(&outside_snapshot) {
+        if txids
+            .iter()
+            .any(|txid| !positions.contains_key(txid.as_str()))
+        {
+            verdict.rejection = Some(RejectReason::MissingInputs);
+        }
+    }
+
+    // a transaction spending an output of a mempool transaction that was turned down can't go
+    // into a block either, and neither can anything below it
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); arena.len()];
+    for (i, record) in arena.iter() {
+        for &p in &record.parents {
+            children[p as usize].push(i as usize);
+        }
+    }
+    let mut stack: Vec<usize> = (0..verdicts.len())
+        .filter(|&i| verdicts[i].rejection.is_some())
+        .collect();
+    while let Some(p) = stack.pop() {
+        for &child in &children[p] {
+            if verdicts[child].rejection.is_none() {
+                verdicts[child].rejection = Some(RejectReason::RejectedParent);
+                stack.push(child);
             }
-            tx_to_tx_node.insert(tx.clone(), tx_node);
-            valid_tx_vector.push(tx.clone());
         }
     }
 
-    // make graph of parent child relationships
-    let mut all_ins: HashSet<String> = HashSet::new();
-    let mut all_outs: HashSet<String> = HashSet::new();
-    let mut scriptpubkey_to_tx: HashMap<String, Transaction> = HashMap::new(); // out to transaction mapping
+    for reason in verdicts.iter().filter_map(|verdict| verdict.rejection) {
+        report.record_rejection(reason);
+    }
+    let valid: Vec<TxNode> = arena
+        .iter()
+        .filter(|(i, _)| verdicts[*i as usize].rejection.is_none())
+        .map(|(index, record)| TxNode {
+            index,
+            txid: record.txid.clone(),
+            fee: record.fee,
+            weight: record.weight,
+        })
+        .collect();
+
+    sig_cache.save()?;
+    report.signature_cache_hits = sig_cache.hits;
+
+    Ok(LoadedMempool {
+        arena,
+        valid,
+        verdicts,
+        report,
+    })
+}
 
-    for tx in valid_tx_vector.clone() {
-        let tx_clone = tx.clone();
+pub fn build_template(config: &Config) -> Result<(BlockTemplate, MempoolReport), Box<dyn Error>> {
+    let mut mempool = load_mempool(config)?;
 
-        for ins in tx.vin {
-            all_ins.insert(ins.prevout.scriptpubkey.clone());
+    let selected = match config.algorithm {
+        SelectionAlgorithm::Greedy => {
+            selection::select_greedy(&mempool.valid, &mempool.arena, config.max_weight)
+        }
+        SelectionAlgorithm::AncestorFeerate => {
+            selection::select_by_ancestor_feerate(&mempool.valid, &mempool.arena, config.max_weight)
+        }
+    };
+
+    let txids: Vec<String> = selected.iter().map(|node| node.txid.clone()).collect();
+    let fees: u64 = selected.iter().map(|node| node.fee).sum();
+    let weight: u64 = selected.iter().map(|node| node.weight).sum();
+
+    let mut wtxids: Vec<Vec<u8>> = vec![vec![0u8; 32]];
+    wtxids.extend(
+        selected
+            .iter()
+            .map(|node| mempool.arena[node.index].wtxid.to_vec()),
+    );
+
+    // get coinbase transaction, it only commits to witnesses when the block has some
+    let has_witness = selected
+        .iter()
+        .any(|node| mempool.arena[node.index].has_witness);
+    let merkle_root_wtxid = has_witness.then(|| get_merkle_root_wtxid(&wtxids));
+    let coinbase_parts = get_coinbase_transaction(
+        config.block_height,
+        fees,
+        5000000000,
+        merkle_root_wtxid,
+        config.witness_reserved_value,
+    );
+    let coinbase = coinbase_parts.assemble(&default_extranonce());
+
+    //calculate merkle root, the coinbase is the first leaf
+    let mut block_txids = vec![raw_txid(&hex::decode(&coinbase)?)?];
+    block_txids.extend(txids.iter().cloned());
+    let merkle_root = block_merkle_root(&block_txids)?;
+
+    // analytics over the mempool and the block we just built
+    let valid_nodes: Vec<&TxNode> = mempool.valid.iter().collect();
+    mempool
+        .report
+        .record_block(&valid_nodes, &mempool.arena, &txids);
+
+    let template = BlockTemplate {
+        height: config.block_height,
+        coinbase,
+        coinbase_parts,
+        txids,
+        wtxids,
+        merkle_root,
+        fees,
+        weight,
+    };
+    Ok((template, mempool.report))
+}
+
+// the block as `mine` writes it, together with the report over the mempool
+fn mine_block(config: &Config) -> Result<(Vec<String>, MempoolReport), Box<dyn Error>> {
+    let (template, report) = build_template(config)?;
+
+    //get block header
+    let block_header = get_block_header(template.merkle_root, config.clock.now());
+
+    let lines = match config.output_format {
+        OutputFormat::Text => {
+            let mut blockdata: Vec<String> = Vec::new();
+            blockdata.push(block_header);
+            blockdata.push(template.coinbase);
+            blockdata.extend(template.txids);
+            blockdata
+        }
+        OutputFormat::Json => {
+            let block = serde_json::json!({
+                "header": block_header,
+                "coinbase": template.coinbase,
+                "txids": template.txids,
+            });
+            vec![serde_json::to_string_pretty(&block)?]
+        }
+    };
+    Ok((lines, report))
+}
+
+pub fn mine(config: &Config) -> Result<(), Box<dyn Error>> {
+    let (block, report) = mine_block(config)?;
+    emit(config.output.as_deref(), block)?;
+
+    if let Some(path) = &config.report {
+        let report = match config.output_format {
+            OutputFormat::Text => report.to_string(),
+            OutputFormat::Json => report.to_json(),
+        };
+        emit(Some(path.as_str()).filter(|p| *p != "-"), vec![report])?;
+    }
+    Ok(())
+}
+
+pub fn template(config: &Config) -> Result<(), Box<dyn Error>> {
+    let (template, _) = build_template(config)?;
+
+    match config.output_format {
+        OutputFormat::Text => {
+            let mut lines = vec![
+                format!("height {}", template.height),
+                format!("merkle_root {}", hex::encode(template.merkle_root)),
+                format!("fees {}", template.fees),
+                format!("weight {}", template.weight),
+                format!("coinbase {}", template.coinbase),
+            ];
+            lines.extend(template.txids);
+            emit(config.output.as_deref(), lines)
+        }
+        OutputFormat::Json => {
+            let json = serde_json::json!({
+                "height": template.height,
+                "merkle_root": hex::encode(template.merkle_root),
+                "fees": template.fees,
+                "weight": template.weight,
+                "coinbase": template.coinbase,
+                "txids": template.txids,
+                "wtxids": template.wtxids.iter().map(hex::encode).collect::<Vec<_>>(),
+            });
+            emit(
+                config.output.as_deref(),
+                vec![serde_json::to_string_pretty(&json)?],
+            )
         }
+    }
+}
 
-        for outs in tx.vout {
-            all_outs.insert(outs.scriptpubkey.clone());
-            scriptpubkey_to_tx.insert(outs.scriptpubkey.clone(), tx_clone.clone());
+pub fn validate(config: &Config) -> Result<(), Box<dyn Error>> {
+    let mempool = load_mempool(config)?;
+
+    match config.output_format {
+        OutputFormat::Text => {
+            let mut lines: Vec<String> = mempool
+                .verdicts
+                .iter()
+                .map(|verdict| {
+                    let mut line = match verdict.rejection {
+                        None => format!("{} ok", verdict.txid),
+                        Some(reason) => format!("{} rejected {}", verdict.txid, reason.as_str()),
+                    };
+                    if !verdict.asm_mismatches.is_empty() {
+                        line.push_str(" asm_mismatch ");
+                        line.push_str(&verdict.asm_mismatches.join(","));
+                    }
+                    if !verdict.address_mismatches.is_empty() {
+                        line.push_str(" address_mismatch ");
+                        line.push_str(&verdict.address_mismatches.join(","));
+                    }
+                    if !verdict.type_mismatches.is_empty() {
+                        line.push_str(" type_mismatch ");
+                        line.push_str(&verdict.type_mismatches.join(","));
+                    }
+                    line
+                })
+                .collect();
+            lines.push(format!(
+                "valid {} / {}",
+                mempool.valid.len(),
+                mempool.verdicts.len()
+            ));
+            emit(config.output.as_deref(), lines)
+        }
+        OutputFormat::Json => {
+            let verdicts: Vec<serde_json::Value> = mempool
+                .verdicts
+                .iter()
+                .map(|verdict| {
+                    serde_json::json!({
+                        "txid": verdict.txid,
+                        "valid": verdict.rejection.is_none(),
+                        "reason": verdict.rejection.map(|r| r.as_str()),
+                        "asm_mismatches": verdict.asm_mismatches,
+                        "address_mismatches": verdict.address_mismatches,
+                        "type_mismatches": verdict.type_mismatches,
+                    })
+                })
+                .collect();
+            emit(
+                config.output.as_deref(),
+                vec![serde_json::to_string_pretty(&verdicts)?],
+            )
         }
     }
+}
 
-    let mut graph: HashMap<TxNode, Vec<TxNode>> = HashMap::new(); // need a scriptpubkey to tx mapping
+// a block as `mine` writes it, either format
+pub(crate) struct MinedBlock {
+    pub(crate) header: Vec<u8>,
+    pub(crate) coinbase: String,
+    pub(crate) txids: Vec<String>,
+}
 
-    for tx in valid_tx_vector {
-        let tx_clone = tx.clone();
-        let curr_tx_node = tx_to_tx_node.get(&tx_clone).unwrap();
+impl MinedBlock {
+    pub(crate) fn read(path: &str) -> Result<MinedBlock, Box<dyn Error>> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+
+        let (header, coinbase, txids) = if contents.trim_start().starts_with('{') {
+            let block: serde_json::Value = serde_json::from_str(&contents)?;
+            let field = |name: &str| -> Result<String, String> {
+                block[name]
+                    .as_str()
+                    .map(|v| v.to_string())
+                    .ok_or(format!("{}: missing {}", path, name))
+            };
+            let txids = block["txids"]
+                .as_array()
+                .ok_or(format!("{}: missing txids", path))?
+                .iter()
+                .filter_map(|txid| txid.as_str().map(|t| t.to_string()))
+                .collect();
+            (field("header")?, field("coinbase")?, txids)
+        } else {
+            let mut lines = contents.lines().map(|l| l.trim().to_string());
+            let header = lines.next().ok_or(format!("{}: missing header", path))?;
+            let coinbase = lines.next().ok_or(format!("{}: missing coinbase", path))?;
+            (header, coinbase, lines.filter(|l| !l.is_empty()).collect())
+        };
 
-        for ins in tx.vin {
-            if all_outs.contains(&ins.prevout.scriptpubkey.clone()) {
-                let parent_tx = scriptpubkey_to_tx.get(&ins.prevout.scriptpubkey).unwrap();
-                let parent_tx_node = tx_to_tx_node.get(parent_tx).unwrap().clone();
-                graph
-                    .entry(parent_tx_node)
-                    .or_insert_with(Vec::new)
-                    .push(curr_tx_node.clone());
-            }
+        let header = hex::decode(header)?;
+        if header.len() != 80 {
+            return Err(format!("{}: header is {} bytes, not 80", path, header.len()).into());
         }
+        Ok(MinedBlock {
+            header,
+            coinbase,
+            txids,
+        })
+    }
 
-        // Create the hashmap for this entry in the graph
-        graph.entry(curr_tx_node.clone()).or_insert_with(Vec::new);
+    // txids of every transaction in the block, the coinbase first
+    pub(crate) fn all_txids(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut txids = vec![raw_txid(&hex::decode(&self.coinbase)?)?];
+        txids.extend(self.txids.iter().cloned());
+        Ok(txids)
     }
+}
 
-    let mut heap = BinaryHeap::new();
+// SPV proofs of inclusion for the given txids in the block written by `mine`
+pub fn prove(txids: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let block = MinedBlock::read(&config.block_file)?;
+    let block_txids = block.all_txids()?;
+    let leaves = block_txids
+        .iter()
+        .map(|txid| merkle::txid_to_bytes(txid))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    // add all the nodes with no incoming edges to the heap
+    if block_merkle_root(&block_txids)?[..] != block.header[36..68] {
+        return Err("merkle root in the header doesn't match the block's transactions".into());
+    }
 
-    for (node, children) in graph.iter() {
-        if children.len() == 0 {
-            heap.push(node.clone());
+    let mut matches = vec![false; leaves.len()];
+    let mut branches = Vec::new();
+    for txid in txids {
+        let index = block_txids
+            .iter()
+            .position(|t| t == txid)
+            .ok_or(format!("{} is not in the block", txid))?;
+        matches[index] = true;
+        branches.push((txid, merkle::merkle_branch(&leaves, index).unwrap()));
+    }
+
+    let tree = merkle::PartialMerkleTree::build(&leaves, &matches);
+    let merkle_block = hex::encode(merkle::merkle_block(&block.header, &tree));
+
+    // branch hashes are shown in display order like txids
+    let display = |hash: &[u8; 32]| {
+        let mut hash = *hash;
+        hash.reverse();
+        hex::encode(hash)
+    };
+
+    match config.output_format {
+        OutputFormat::Text => {
+            let mut lines = vec![format!("merkleblock {}", merkle_block)];
+            for (txid, branch) in &branches {
+                let hashes: Vec<String> = branch.hashes.iter().map(display).collect();
+                lines.push(format!(
+                    "{} index {} branch {}",
+                    txid,
+                    branch.index,
+                    hashes.join(",")
+                ));
+            }
+            emit(config.output.as_deref(), lines)
+        }
+        OutputFormat::Json => {
+            let proofs: Vec<serde_json::Value> = branches
+                .iter()
+                .map(|(txid, branch)| {
+                    serde_json::json!({
+                        "txid": txid,
+                        "index": branch.index,
+                        "branch": branch.hashes.iter().map(display).collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            let json = serde_json::json!({
+                "merkleblock": merkle_block,
+                "proofs": proofs,
+            });
+            emit(
+                config.output.as_deref(),
+                vec![serde_json::to_string_pretty(&json)?],
+            )
         }
     }
+}
+
+// consensus checks on the block written by `mine`, the problems found go to the output
+pub fn check_block(config: &Config) -> Result<(), Box<dyn Error>> {
+    let block = MinedBlock::read(&config.block_file)?;
+    let mut problems = Vec::new();
+
+    // proof of work against the target encoded in the header's bits
+    let bits = u32::from_le_bytes(block.header[72..76].try_into().unwrap());
+    let target = target_from_bits(bits);
+    let mut hash = sha256_hash(&sha256_hash(&block.header));
+    hash.reverse();
+    if BigUint::from_bytes_be(&hash) > target {
+        problems.push(format!(
+            "header hash {} is above the target",
+            hex::encode(&hash)
+        ));
+    }
+
+    let coinbase = match rawtx::decode_raw_transaction(&hex::decode(&block.coinbase)?) {
+        Ok(coinbase) if coinbase.vin.len() == 1 && coinbase.vin[0].is_coinbase => Some(coinbase),
+        Ok(_) => {
+            problems.push("first transaction is not a coinbase".to_string());
+            None
+        }
+        Err(e) => {
+            problems.push(format!("coinbase doesn't decode: {}", e));
+            None
+        }
+    };
 
-    let mut MAX_WEIGHT: u64 = 4000000;
-    let mut block_weight: u64 = 0;
-    let mut fees: u64 = 0;
-    let mut accepted_txs: Vec<String> = Vec::new();
-    let mut wtxid_strings = Vec::new();
-    let mut i = 0;
-    while let Some(node) = heap.pop() {
-        // if the weight of the block after adding the node is less than the max weight, add the node to the block
-        if block_weight + node.weight <= MAX_WEIGHT {
-            block_weight += node.weight;
-            fees += node.fee;
-            accepted_txs.push(node.txid.clone());
-            if i != 0 {
-                let wtxid = get_wtxid(node.tx.clone());
-                wtxid_strings.push(node.txid.clone() + " " + &hex::encode(wtxid.clone()));
-                valid_wtxid.push(wtxid.clone());
+    // the witness commitment and the order of parents need the transactions, they come from
+    // the mempool
+    let arena = TxArena::read_dir(&config.mempool_dir, |_, _| {}).unwrap_or_default();
+    let positions = arena.positions();
+    let records: Vec<&TxRecord> = block
+        .txids
+        .iter()
+        .filter_map(|txid| positions.get(txid.as_str()).map(|&i| &arena[i]))
+        .collect();
+
+    let mut notes = Vec::new();
+    if let Some(coinbase) = &coinbase {
+        if records.len() == block.txids.len() {
+            let mut wtxids: Vec<Vec<u8>> = vec![vec![0u8; 32]];
+            wtxids.extend(records.iter().map(|record| record.wtxid.to_vec()));
+            let has_witness = records.iter().any(|record| record.has_witness);
+            if let Err(e) = check_witness_commitment(coinbase, &wtxids, has_witness) {
+                problems.push(e);
             }
+        } else {
+            notes.push(format!(
+                "witness commitment not checked, {} transactions are not in {}",
+                block.txids.len() - records.len(),
+                config.mempool_dir
+            ));
+        }
+    }
 
-            if let Some(children) = graph.get(&node).cloned() {
-                for child in children {
-                    let mut incomings = graph.get_mut(&child).unwrap();
-                    incomings.remove(0);
-                    if incomings.is_empty() {
-                        heap.push(tx_to_tx_node.get(&child.tx.clone()).unwrap().clone());
-                    }
+    let parents: HashMap<&str, Vec<&str>> = records
+        .iter()
+        .map(|record| {
+            let parents = record.parents.iter().map(|&p| arena[p].txid.as_str());
+            (record.txid.as_str(), parents.collect())
+        })
+        .collect();
+    problems.extend(parent_problems(&block.txids, &parents));
+
+    let block_txids = block.all_txids()?;
+    let mut seen = HashSet::new();
+    for txid in &block_txids {
+        if !seen.insert(txid) {
+            problems.push(format!("duplicate transaction {}", txid));
+        }
+    }
+
+    let leaves = block_txids
+        .iter()
+        .map(|txid| merkle::txid_to_bytes(txid))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (root, mutated) = merkle::compute_merkle_root(&leaves);
+    if mutated {
+        problems.push("merkle tree is mutated (CVE-2012-2459)".to_string());
+    }
+    if root[..] != block.header[36..68] {
+        problems.push("merkle root in the header doesn't match the transactions".to_string());
+    }
+
+    if problems.is_empty() {
+        let mut lines = vec![format!("block ok, {} transactions", block_txids.len())];
+        lines.extend(notes);
+        emit(config.output.as_deref(), lines)
+    } else {
+        emit(config.output.as_deref(), problems)?;
+        Err("block is invalid".into())
+    }
+}
+
+// every mempool transaction a block's transaction spends has to be in the block ahead of it.
+// `parents` holds the txids each transaction spends that are in the mempool
+fn parent_problems(txids: &[String], parents: &HashMap<&str, Vec<&str>>) -> Vec<String> {
+    let position: HashMap<&str, usize> = txids
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, txid)| (txid.as_str(), i))
+        .collect();
+
+    let mut problems = Vec::new();
+    for (i, txid) in txids.iter().enumerate() {
+        let mut spent: Vec<&str> = parents.get(txid.as_str()).cloned().unwrap_or_default();
+        spent.sort_unstable();
+        spent.dedup();
+        for parent in spent {
+            match position.get(parent) {
+                None => problems.push(format!(
+                    "{} spends {}, which isn't in the block",
+                    txid, parent
+                )),
+                Some(&p) if p > i => {
+                    problems.push(format!("{} spends {}, which comes after it", txid, parent))
                 }
+                _ => {}
             }
         }
-        i = i + 1;
     }
-    //calculate merkle root
-    let merkle_root = get_merkle_root(accepted_txs.clone());
+    problems
+}
 
-    //get block header
-    let block_header = get_block_header(merkle_root.clone());
+// BIP152 compact block of the block written by `mine`, its transactions come from the mempool
+pub fn compact(config: &Config) -> Result<(), Box<dyn Error>> {
+    let block = MinedBlock::read(&config.block_file)?;
+    let arena = TxArena::read_dir(&config.mempool_dir, |_, _| {})?;
+    let positions = arena.positions();
+    let records = block
+        .txids
+        .iter()
+        .map(|txid| {
+            positions
+                .get(txid.as_str())
+                .map(|&i| &arena[i])
+                .ok_or(format!("{} is not in {}", txid, config.mempool_dir))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // the nonce only salts the short ids, taking it from the header keeps runs reproducible
+    let nonce = u64::from_le_bytes(sha256_hash(&block.header)[..8].try_into().unwrap());
+    let coinbase = hex::decode(&block.coinbase)?;
+    let wtxids: Vec<[u8; 32]> = records.iter().map(|record| record.wtxid).collect();
+    let compact_block = compact::CompactBlock::build(&block.header, nonce, &coinbase, &wtxids);
+    let raw = compact_block.serialize();
+
+    let full_size = block.header.len()
+        + turn_to_varint(records.len() as u64 + 1).len()
+        + coinbase.len()
+        + records
+            .iter()
+            .map(|record| record.size as usize)
+            .sum::<usize>();
+
+    match config.output_format {
+        OutputFormat::Text => emit(
+            config.output.as_deref(),
+            vec![
+                format!("cmpctblock {}", hex::encode(&raw)),
+                format!(
+                    "transactions {} short_ids {} prefilled {}",
+                    compact_block.transaction_count(),
+                    compact_block.short_ids.len(),
+                    compact_block.prefilled.len()
+                ),
+                format!("size {} bytes, full block {} bytes", raw.len(), full_size),
+            ],
+        ),
+        OutputFormat::Json => {
+            let json = serde_json::json!({
+                "cmpctblock": hex::encode(&raw),
+                "transactions": compact_block.transaction_count(),
+                "short_ids": compact_block.short_ids.len(),
+                "prefilled": compact_block.prefilled.len(),
+                "size": raw.len(),
+                "full_block_size": full_size,
+            });
+            emit(
+                config.output.as_deref(),
+                vec![serde_json::to_string_pretty(&json)?],
+            )
+        }
+    }
+}
 
-    let merkle_root_wtxid = get_merkle_root_wtxid(&valid_wtxid.clone());
-    // get coinbase transaction
-    let coinbase_transaction =
-        get_coinbase_transaction(69, fees, 5000000000, merkle_root_wtxid.clone().to_vec());
+// rebuilds a block from a compact block (a file or the hex itself) and the local mempool
+pub fn reconstruct(source: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    let contents = match fs::read_to_string(source) {
+        Ok(contents) => contents,
+        Err(_) if hex::decode(source.trim()).is_ok() => source.to_string(),
+        Err(e) => return Err(format!("{}: {}", source, e).into()),
+    };
+    // accepts the `compact` output as is
+    let hex_data = contents
+        .split_whitespace()
+        .find(|word| *word != "cmpctblock")
+        .unwrap_or_default();
+    let compact_block = compact::CompactBlock::deserialize(&hex::decode(hex_data)?)?;
+
+    // streamed, only the transactions the block asks for are kept
+    let reconstruction = compact_block.reconstruct(arena::stream_dir(&config.mempool_dir)?)?;
+    let total = compact_block.transaction_count();
+
+    // with every transaction in hand the header's merkle root can be checked
+    let merkle_root_ok = if reconstruction.missing.is_empty() {
+        let txids = reconstruction
+            .txs
+            .iter()
+            .map(|raw| raw_txid(raw.as_ref().unwrap()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Some(block_merkle_root(&txids)?[..] == compact_block.header[36..68])
+    } else {
+        None
+    };
 
-    let mut blockdata: Vec<String> = Vec::new();
-    blockdata.push(block_header);
-    blockdata.push(coinbase_transaction);
-    blockdata.extend(accepted_txs);
+    match config.output_format {
+        OutputFormat::Text => {
+            let mut lines = vec![format!(
+                "reconstructed {} / {}",
+                total - reconstruction.missing.len(),
+                total
+            )];
+            for index in &reconstruction.missing {
+                lines.push(format!("missing {}", index));
+            }
+            match merkle_root_ok {
+                Some(true) => lines.push("merkle root ok".to_string()),
+                Some(false) => lines.push("merkle root mismatch".to_string()),
+                None => {}
+            }
+            emit(config.output.as_deref(), lines)
+        }
+        OutputFormat::Json => {
+            let json = serde_json::json!({
+                "transactions": total,
+                "reconstructed": total - reconstruction.missing.len(),
+                "missing": reconstruction.missing,
+                "merkle_root_ok": merkle_root_ok,
+            });
+            emit(
+                config.output.as_deref(),
+                vec![serde_json::to_string_pretty(&json)?],
+            )
+        }
+    }
+}
 
-    // Output the block in a output.txt file
-    write_to_file(blockdata, "./output.txt").unwrap();
+// the template as a Stratum v1 job: the mining.subscribe result and the mining.notify message
+pub fn stratum_job(config: &Config) -> Result<(), Box<dyn Error>> {
+    let (template, _) = build_template(config)?;
+    let job = stratum::StratumJob::from_template(&template, config.clock.now())?;
+    emit(config.output.as_deref(), job.to_messages())
+}
+
+// checks a mining.submit share against the job written by `stratum-job`
+pub fn submit_share(
+    job_file: &str,
+    extranonce2: &str,
+    ntime: &str,
+    nonce: &str,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let job = stratum::StratumJob::from_messages(&fs::read_to_string(job_file)?)
+        .map_err(|e| format!("{}: {}", job_file, e))?;
+    let extranonce2 =
+        hex::decode(extranonce2).map_err(|_| format!("extranonce2 is not hex: {}", extranonce2))?;
+    let ntime =
+        u32::from_str_radix(ntime, 16).map_err(|_| format!("ntime is not hex: {}", ntime))?;
+    let nonce =
+        u32::from_str_radix(nonce, 16).map_err(|_| format!("nonce is not hex: {}", nonce))?;
+    let share_target = match &config.share_target {
+        Some(target) => BigUint::from_bytes_be(&hex::decode(target)?),
+        None => target_from_bits(job.bits),
+    };
+
+    let check = job.check_share(&extranonce2, ntime, nonce, &share_target)?;
+    match config.output_format {
+        OutputFormat::Text => emit(
+            config.output.as_deref(),
+            vec![
+                format!("header {}", hex::encode(&check.header)),
+                format!("hash {}", hex::encode(check.hash)),
+                format!(
+                    "share {}",
+                    if check.meets_share_target {
+                        "accepted"
+                    } else {
+                        "rejected"
+                    }
+                ),
+                format!(
+                    "block {}",
+                    if check.meets_block_target {
+                        "found"
+                    } else {
+                        "not found"
+                    }
+                ),
+            ],
+        )?,
+        OutputFormat::Json => {
+            let json = serde_json::json!({
+                "header": hex::encode(&check.header),
+                "hash": hex::encode(check.hash),
+                "accepted": check.meets_share_target,
+                "block": check.meets_block_target,
+            });
+            emit(
+                config.output.as_deref(),
+                vec![serde_json::to_string_pretty(&json)?],
+            )?
+        }
+    }
+
+    if !check.meets_share_target {
+        return Err("share is above the target".into());
+    }
+    Ok(())
+}
+
+// writes to the given file, or to stdout when there is none
+fn emit(output: Option<&str>, lines: Vec<String>) -> Result<(), Box<dyn Error>> {
+    match output {
+        Some(filename) => write_to_file(lines, filename),
+        None => {
+            println!("{}", lines.join("\n"));
+            Ok(())
+        }
+    }
 }
 
 fn correct_cal_weight(non_witness: Vec<u8>, witness_and_markerflag: Vec<u8>) -> u64 {
     return (non_witness.len() as u64) * 4 + (witness_and_markerflag.len() as u64);
 }
 
-fn check_locktime(tx: Transaction, block_height: u32) -> bool {
+fn check_locktime(tx: Transaction, block_height: u32, current_time: u32) -> bool {
     if tx.vin.iter().all(|input| input.sequence == 0xFFFFFFFF) {
         return true;
     }
@@ -237,10 +1062,6 @@ fn check_locktime(tx: Transaction, block_height: u32) -> bool {
         }
     } else {
         // locktime is interpreted as a Unix timestamp
-        let current_time = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs() as u32;
         if tx.locktime > current_time {
             return false;
         }
@@ -248,7 +1069,21 @@ fn check_locktime(tx: Transaction, block_height: u32) -> bool {
     true
 }
 
-fn get_block_header(merkle_root: [u8; 32]) -> String {
+// header fields every block we mine shares
+pub const BLOCK_VERSION: u32 = 0x00000004;
+pub const BLOCK_BITS: u32 = 0x1f00ffff;
+
+// expands the compact target of a header's bits field
+pub(crate) fn target_from_bits(bits: u32) -> BigUint {
+    let exponent = bits >> 24;
+    if exponent <= 3 {
+        BigUint::from(bits & 0x007fffff) >> (8 * (3 - exponent))
+    } else {
+        BigUint::from(bits & 0x007fffff) << (8 * (exponent - 3))
+    }
+}
+
+fn get_block_header(merkle_root: [u8; 32], time: u32) -> String {
     let mut nonce: u32 = 0;
     let mut block_header: String = "".to_string();
 
@@ -261,8 +1096,7 @@ fn get_block_header(merkle_root: [u8; 32]) -> String {
         let mut predigest: Vec<u8> = Vec::new();
 
         //add version
-        let version: u32 = 0x00000004;
-        predigest.extend_from_slice(&version.to_le_bytes());
+        predigest.extend_from_slice(&BLOCK_VERSION.to_le_bytes());
 
         //add prev block hash
         let prev_block_hash = vec![0u8; 32]; // This should be the hash of the previous block
@@ -272,14 +1106,10 @@ fn get_block_header(merkle_root: [u8; 32]) -> String {
         predigest.extend_from_slice(&merkle_root);
 
         //add time
-        let now = SystemTime::now();
-        let since_the_epoch = now.duration_since(UNIX_EPOCH).expect("Time went backwards");
-        let time = since_the_epoch.as_secs() as u32;
         predigest.extend_from_slice(&time.to_le_bytes());
 
         //add target
-        let bits: u32 = 0xffff001f;
-        predigest.extend_from_slice(&bits.to_be_bytes());
+        predigest.extend_from_slice(&BLOCK_BITS.to_le_bytes());
 
         //add nonce
         predigest.extend_from_slice(&nonce.to_le_bytes());
@@ -306,24 +1136,68 @@ fn write_to_file(block: Vec<String>, filename: &str) -> Result<(), Box<dyn Error
     Ok(())
 }
 
+// extranonce the coinbase carries when nobody else picks one: the pool's part (which used to
+// be the only random data) and the miner's part
+pub const EXTRANONCE1: [u8; 4] = [0x96, 0x69, 0x96, 0x69];
+pub const EXTRANONCE2_SIZE: usize = 4;
+
+fn default_extranonce() -> Vec<u8> {
+    let mut extranonce = EXTRANONCE1.to_vec();
+    extranonce.extend_from_slice(&[0u8; EXTRANONCE2_SIZE]);
+    extranonce
+}
+
+// legacy serialization of the coinbase cut around the extranonce, as handed to stratum miners
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoinbaseParts {
+    pub coinb1: Vec<u8>,
+    pub coinb2: Vec<u8>,
+    // the coinbase's only witness item, None when the block has no witness data and the
+    // coinbase commits to none
+    pub witness_reserved_value: Option<[u8; 32]>,
+}
+
+impl CoinbaseParts {
+    // the full serialization, with marker, flag and the witness reserved value when there is one
+    pub fn assemble(&self, extranonce: &[u8]) -> String {
+        let mut tx: Vec<u8> = Vec::new();
+        let Some(reserved_value) = self.witness_reserved_value else {
+            tx.extend_from_slice(&self.coinb1);
+            tx.extend_from_slice(extranonce);
+            tx.extend_from_slice(&self.coinb2);
+            return hex::encode(tx);
+        };
+
+        tx.extend_from_slice(&self.coinb1[..4]);
+        tx.push(0x00);
+        tx.push(0x01);
+        tx.extend_from_slice(&self.coinb1[4..]);
+        tx.extend_from_slice(extranonce);
+        tx.extend_from_slice(&self.coinb2[..self.coinb2.len() - 4]);
+
+        // one witness item of 32 bytes
+        tx.push(0x01);
+        tx.push(0x20);
+        tx.extend_from_slice(&reserved_value);
+
+        tx.extend_from_slice(&self.coinb2[self.coinb2.len() - 4..]);
+        hex::encode(tx)
+    }
+}
+
 fn get_coinbase_transaction(
     block_height: u32,
     fees: u64,
     block_reward: u64,
-    witness_root_hash: Vec<u8>,
-) -> String {
+    witness_root_hash: Option<Vec<u8>>,
+    witness_reserved_value: [u8; 32],
+) -> CoinbaseParts {
     let mut tx: Vec<u8> = Vec::new();
 
     // add version
     let version: u32 = 0x00000002;
     tx.extend_from_slice(&version.to_le_bytes());
 
-    // add marker and flag
-    let marker: u8 = 0x00;
-    tx.push(marker);
-    let flag: u8 = 0x01;
-    tx.push(flag);
-
     // add input count
     let input: u8 = 0x01;
     tx.push(input);
@@ -337,7 +1211,7 @@ fn get_coinbase_transaction(
     let output_value: u32 = 0xffffffff;
     tx.extend_from_slice(&output_value.to_le_bytes());
 
-    // place coinbase
+    // place coinbase: the height, then a push of the extranonce
     let mut coinbase: Vec<u8> = Vec::new();
     let mut temp: Vec<u8> = Vec::new();
 
@@ -348,24 +1222,28 @@ fn get_coinbase_transaction(
 
     coinbase.extend_from_slice(&temp);
 
-    let mut temp2: Vec<u8> = Vec::new();
-    let random_data: u32 = 0x69966996;
-    temp2.extend_from_slice(&random_data.to_le_bytes());
-    coinbase.push(temp2.len() as u8);
-
-    coinbase.extend_from_slice(&temp2);
+    let extranonce_size = EXTRANONCE1.len() + EXTRANONCE2_SIZE;
+    coinbase.push(extranonce_size as u8);
 
-    let coinbase_len = coinbase.len() as u64;
+    let coinbase_len = (coinbase.len() + extranonce_size) as u64;
     let coinbase_varint = turn_to_varint(coinbase_len);
     tx.extend_from_slice(&coinbase_varint[..]);
     tx.extend_from_slice(&coinbase);
 
+    // everything after the extranonce
+    let coinb1 = tx;
+    let mut tx: Vec<u8> = Vec::new();
+
     //add sequence
     let sequence: u32 = 0xffffffff;
     tx.extend_from_slice(&sequence.to_le_bytes());
 
-    // add output count
-    let output: u8 = 0x02;
+    // add output count, the witness commitment is left out for blocks without witness data
+    let output: u8 = if witness_root_hash.is_some() {
+        0x02
+    } else {
+        0x01
+    };
     tx.push(output);
 
     // add value of the output
@@ -378,130 +1256,237 @@ fn get_coinbase_transaction(
     tx.push(script.len() as u8);
     tx.extend_from_slice(&script);
 
-    // add value of the output
-    let output_value: u64 = 0x0000000000000000;
-    tx.extend_from_slice(&output_value.to_le_bytes());
+    if let Some(witness_root_hash) = &witness_root_hash {
+        // add value of the output
+        let output_value: u64 = 0x0000000000000000;
+        tx.extend_from_slice(&output_value.to_le_bytes());
 
-    //add script
-    let mut commit: Vec<u8> = Vec::new();
-    let commit_data = "6a24aa21a9ed";
-    let commit_data = hex::decode(commit_data).unwrap();
-    commit.extend_from_slice(&commit_data);
-
-    let mut script: Vec<u8> = Vec::new();
-    script.extend_from_slice(&witness_root_hash);
-
-    let witness_reserved_value = "0000000000000000000000000000000000000000000000000000000000000000";
-    let witness_reserved_value = hex::decode(witness_reserved_value).unwrap();
-    script.extend_from_slice(&witness_reserved_value[..]);
-    let hash = sha256_hash(&sha256_hash(&script));
-    commit.extend_from_slice(&hash);
-    tx.push(commit.len() as u8);
-    tx.extend_from_slice(&commit);
-
-    // add number of witnesses
-    let witness: u8 = 0x01;
-    tx.push(witness);
-
-    // add witness
-    let witness_size: u8 = 0x20;
-    tx.push(witness_size);
-
-    let witness_data = "0000000000000000000000000000000000000000000000000000000000000000";
-    let witness_data = hex::decode(witness_data).unwrap();
-    tx.extend_from_slice(&witness_data);
+        //add script
+        let commit = witness_commitment_script(witness_root_hash, &witness_reserved_value);
+        tx.push(commit.len() as u8);
+        tx.extend_from_slice(&commit);
+    }
 
     // add locktime
     let locktime: u32 = 0x00000000;
     tx.extend_from_slice(&locktime.to_le_bytes());
 
-    let tx_to_string = hex::encode(tx);
-
-    tx_to_string
+    CoinbaseParts {
+        coinb1,
+        coinb2: tx,
+        witness_reserved_value: witness_root_hash.map(|_| witness_reserved_value),
+    }
 }
 
-fn get_merkle_root(mut accepted_txns: Vec<String>) -> [u8; 32] {
-    let mut merkle_root: Vec<[u8; 32]> = Vec::new();
-    let mut temp_array: Vec<[u8; 32]> = Vec::new();
-
-    if accepted_txns.len() % 2 == 1 {
-        accepted_txns.push(accepted_txns.last().unwrap().clone());
-    }
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
 
-    for tx in accepted_txns {
-        //change endianness here as all string we get change the endianess
+// OP_RETURN push of sha256d(witness root || reserved value), BIP141
+fn witness_commitment_script(witness_root_hash: &[u8], witness_reserved_value: &[u8]) -> Vec<u8> {
+    let mut preimage = witness_root_hash.to_vec();
+    preimage.extend_from_slice(witness_reserved_value);
 
-        let txid = hex::decode(tx).unwrap();
+    let mut commit = WITNESS_COMMITMENT_HEADER.to_vec();
+    commit.extend_from_slice(&sha256_hash(&sha256_hash(&preimage)));
+    commit
+}
 
-        //change endianness
-        let reversed_txid: Vec<u8> = txid.iter().rev().cloned().collect();
+// when several outputs look like a commitment only the last one counts
+fn witness_commitment_index(coinbase: &Transaction) -> Option<usize> {
+    coinbase.vout.iter().rposition(|out| {
+        hex::decode(&out.scriptpubkey).is_ok_and(|script| {
+            script.len() >= 38 && script.starts_with(&WITNESS_COMMITMENT_HEADER)
+        })
+    })
+}
 
-        // let hash = sha256_hash(&sha256_hash(&reversed_txid));
-        let rev_txid_in_bytes: [u8; 32] = match reversed_txid.try_into() {
-            Ok(arr) => arr,
-            Err(_) => panic!("Expected a Vec of length 32"),
-        };
+// the commitment rules of BIP141: with a commitment the coinbase witness is a single 32 byte
+// reserved value and the commitment matches the block's wtxids (display order, coinbase's
+// all zero one first), without one no transaction may carry witness data
+pub(crate) fn check_witness_commitment(
+    coinbase: &Transaction,
+    wtxids: &[Vec<u8>],
+    has_witness: bool,
+) -> Result<(), String> {
+    let coinbase_witness = coinbase.vin.first().and_then(|ins| ins.witness.as_ref());
+
+    let Some(index) = witness_commitment_index(coinbase) else {
+        if has_witness || coinbase_witness.is_some() {
+            return Err("witness data without a witness commitment".to_string());
+        }
+        return Ok(());
+    };
 
-        merkle_root.push(rev_txid_in_bytes.try_into().unwrap());
+    let reserved_value = match coinbase_witness.map(|witness| witness.as_slice()) {
+        Some([item]) if item.len() == 64 => hex::decode(item).map_err(|e| e.to_string())?,
+        _ => return Err("coinbase witness has to be a single 32 byte reserved value".to_string()),
+    };
+    let expected = witness_commitment_script(&get_merkle_root_wtxid(wtxids), &reserved_value);
+    let script = hex::decode(&coinbase.vout[index].scriptpubkey).map_err(|e| e.to_string())?;
+    if script[..38] != expected[..] {
+        return Err(format!(
+            "witness commitment in output {} doesn't match the transactions",
+            index
+        ));
     }
+    Ok(())
+}
 
-    while merkle_root.len() > 1 {
-        if merkle_root.len() % 2 == 1 {
-            merkle_root.push(merkle_root.last().unwrap().clone());
-        }
+// root over displayed txids (or wtxids), in internal byte order as it goes into the header
+fn get_merkle_root(accepted_txns: Vec<String>) -> [u8; 32] {
+    let leaves: Vec<[u8; 32]> = accepted_txns
+        .iter()
+        .map(|txid| merkle::txid_to_bytes(txid).unwrap())
+        .collect();
+    merkle::merkle_root(&leaves)
+}
 
-        temp_array.clear();
-        for chunks in merkle_root.chunks(2) {
-            let mut combined = Vec::new();
-            combined.extend_from_slice(&chunks[0]);
-            if let Some(second) = chunks.get(1) {
-                combined.extend_from_slice(second);
-            }
-            let hash = sha256_hash(&sha256_hash(&combined));
-            temp_array.push(hash.try_into().unwrap());
+// header merkle root of a block with these txids, refusing trees that are mutated
+fn block_merkle_root(txids: &[String]) -> Result<[u8; 32], Box<dyn Error>> {
+    let leaves = txids
+        .iter()
+        .map(|txid| merkle::txid_to_bytes(txid))
+        .collect::<Result<Vec<_>, _>>()?;
+    match merkle::compute_merkle_root(&leaves) {
+        (_, true) => {
+            Err("duplicate transactions make a mutated merkle tree (CVE-2012-2459)".into())
         }
-
-        merkle_root = temp_array.clone();
+        (root, false) => Ok(root),
     }
-
-    merkle_root[0] // is the final merkle root
 }
 
-fn check_input_output(tx: Transaction) -> (bool, u64) {
+fn check_input_output(tx: &Transaction) -> (bool, u64) {
     let mut inputs: u64 = 0;
     let mut outputs: u64 = 0;
 
-    for ins in tx.vin {
+    for ins in &tx.vin {
         inputs = inputs + ins.prevout.value;
     }
 
-    for outs in tx.vout {
+    for outs in &tx.vout {
         outputs = outputs + outs.value;
     }
 
-    (inputs >= outputs, inputs - outputs)
+    (inputs >= outputs, inputs.saturating_sub(outputs))
 }
 
-fn check_sig(tx: Transaction) -> bool {
-    for (index, ins) in tx.vin.iter().enumerate() {
-        match &*ins.prevout.scriptpubkey_type {
-            "v1_p2tr" => {
-                continue;
-            }
-            "v0_p2wpkh" => {
-                let sign_in_witness = ins.witness.clone().unwrap()[0].clone();
-                let sign_to_bytes = hex::decode(sign_in_witness).unwrap();
+fn check_sig(tx: Transaction, sig_cache: &mut SigCache) -> bool {
+    // a transaction that verified before under the same rules isn't verified again
+    let wtxid = hex::encode(get_wtxid(&tx));
+    let prevouts = sigcache::prevouts_hash(&tx);
+    if sig_cache.contains(&wtxid, &prevouts, sigcache::STANDARD_FLAGS) {
+        return true;
+    }
 
-                let sign_to_verify = &sign_to_bytes[..sign_to_bytes.len() - 1];
+    let valid = tx
+        .vin
+        .iter()
+        .enumerate()
+        .all(|(index, ins)| check_input_sig(&tx, index, ins));
+    if valid {
+        sig_cache.insert(wtxid, prevouts, sigcache::STANDARD_FLAGS);
+    }
+    valid
+}
 
-                let pubkey = ins.witness.clone().unwrap()[1].clone();
-                let pubkey_in_bytes_vec = hex::decode(pubkey).unwrap();
-                let pubkey_in_bytes: [u8; 33] = pubkey_in_bytes_vec.clone().try_into().unwrap();
+// verifies the signatures of a single input, input types we can't verify yet pass
+fn check_input_sig(tx: &Transaction, index: usize, ins: &Input) -> bool {
+    // dispatch on the prevout script itself, the scriptpubkey_type label is only cross-checked
+    let prevout = hex::decode(&ins.prevout.scriptpubkey).unwrap_or_default();
+    match classify(&prevout) {
+        ScriptType::P2tr => {
+            return true;
+        }
+        ScriptType::P2wpkh => {
+            // a signature and a public key, a witness of any other shape can't be valid
+            let Some([sign_in_witness, pubkey]) = ins.witness.as_deref() else {
+                return false;
+            };
+            let Ok(sign_to_bytes) = hex::decode(sign_in_witness) else {
+                return false;
+            };
+            let Some((&sighash, sign_to_verify)) = sign_to_bytes.split_last() else {
+                return false;
+            };
+
+            let Ok(pubkey_in_bytes_vec) = hex::decode(pubkey) else {
+                return false;
+            };
+            let Ok(pubkey_in_bytes) = <[u8; 33]>::try_from(pubkey_in_bytes_vec.as_slice()) else {
+                return false;
+            };
+
+            //scriptcode 0x1976a914{20-byte-pubkey-hash}88ac
+            let mut scriptcode: Vec<u8> = Vec::new();
+            scriptcode.push(0x19);
+            scriptcode.push(0x76);
+            scriptcode.push(0xa9);
+            scriptcode.push(0x14);
+            let pub_hash = hash160(&pubkey_in_bytes_vec);
+            scriptcode.extend_from_slice(&pub_hash);
+            scriptcode.push(0x88);
+            scriptcode.push(0xac);
+
+            let hash = get_commitment_hash_segwit(
+                tx.clone(),
+                tx.version,
+                sighash as u32,
+                tx.locktime,
+                scriptcode,
+                ins.sequence,
+                ins.prevout.value,
+                ins.txid.clone(),
+                ins.vout,
+            );
+
+            // verification
+            let (Ok(signature), Ok(pubkey), Ok(msg)) = (
+                Signature::parse_der(sign_to_verify),
+                PublicKey::parse_compressed(&pubkey_in_bytes),
+                Message::parse_slice(&hash),
+            ) else {
+                return false;
+            };
+
+            let ret = verify(&msg, &signature, &pubkey);
+
+            if ret == false {
+                return false;
+            }
+        }
+        ScriptType::P2wsh => {
+            // at least the witness script
+            let Some(witness) = ins.witness.as_deref().filter(|witness| !witness.is_empty()) else {
+                return false;
+            };
+            if !check_multisig_witness(tx, ins, witness) {
+                return false;
+            }
+        }
+        ScriptType::P2sh => {
+            // has 2 nested case + a pure p2sh case
 
+            // if no witness , its pure p2sh
+            let Some(witness) = ins.witness.as_deref().filter(|witness| !witness.is_empty()) else {
+                return false;
+            };
+            if let [sign_in_witness, pubkey] = witness {
+                // nested p2wpkh
+                let Ok(sign_to_bytes) = hex::decode(sign_in_witness) else {
+                    return false;
+                };
                 // extract last byte of the sign
-                let sighash = sign_to_bytes.last().cloned().unwrap();
+                let Some((&sighash, sign_to_verify)) = sign_to_bytes.split_last() else {
+                    return false;
+                };
+
+                let Ok(pubkey_in_bytes_vec) = hex::decode(pubkey) else {
+                    return false;
+                };
+                let Ok(pubkey_in_bytes) = <[u8; 33]>::try_from(pubkey_in_bytes_vec.as_slice())
+                else {
+                    return false;
+                };
 
-                //scriptcode 0x1976a914{20-byte-pubkey-hash}88ac
                 let mut scriptcode: Vec<u8> = Vec::new();
                 scriptcode.push(0x19);
                 scriptcode.push(0x76);
@@ -525,308 +1510,149 @@ fn check_sig(tx: Transaction) -> bool {
                 );
 
                 // verification
-                let signature = Signature::parse_der(&sign_to_verify).unwrap();
-                let pubkey = PublicKey::parse_compressed(&pubkey_in_bytes).unwrap();
-                let msg = Message::parse_slice(&hash).unwrap();
+                let (Ok(signature), Ok(pubkey), Ok(msg)) = (
+                    Signature::parse_der(sign_to_verify),
+                    PublicKey::parse_compressed(&pubkey_in_bytes),
+                    Message::parse_slice(&hash),
+                ) else {
+                    return false;
+                };
 
                 let ret = verify(&msg, &signature, &pubkey);
 
                 if ret == false {
                     return false;
                 }
+            } else if !check_multisig_witness(tx, ins, witness) {
+                // nested p2wsh
+                return false;
             }
-            "v0_p2wsh" => {
-                let witness_len = ins.witness.clone().unwrap().len();
-
-                let mut signatures_vector: Vec<Vec<u8>> = Vec::new();
-                let mut sighash_vector: HashMap<Vec<u8>, u32> = HashMap::new();
-                let mut pubkey_vec: Vec<[u8; 33]> = Vec::new();
-                let mut pubkey_hash_vec: Vec<Vec<u8>> = Vec::new();
-
-                for i in 0..(witness_len - 1) {
-                    let witness_to_bytes =
-                        hex::decode(ins.witness.clone().unwrap()[i].clone()).unwrap();
-
-                    if witness_to_bytes.len() == 0 {
-                        continue;
-                    }
-
-                    let sign_to_verify = witness_to_bytes[..witness_to_bytes.len() - 1].to_vec();
-                    let sighash = witness_to_bytes.last().cloned().unwrap();
+        }
+        ScriptType::P2pkh => {
+            // <sig+sighash> <pubkey>, each behind a one byte push
+            let Ok(scriptsig) = hex::decode(&ins.scriptsig) else {
+                return false;
+            };
+            let Some(&sig_len) = scriptsig.first() else {
+                return false;
+            };
+            let sig_len = sig_len as usize;
+            let Some((&sighash, sig_in_bytes)) = scriptsig
+                .get(1..1 + sig_len)
+                .and_then(|sig_w_sighash| sig_w_sighash.split_last())
+            else {
+                return false;
+            };
 
-                    signatures_vector.push(sign_to_verify.clone());
-                    sighash_vector.insert(sign_to_verify, sighash as u32);
-                }
+            // the rest past the pubkey's push byte, compressed or not
+            let Some(pubkey_in_bytes) = scriptsig.get(2 + sig_len..) else {
+                return false;
+            };
+            let (Ok(pubkey), Ok(sign)) = (
+                PublicKey::parse_slice(pubkey_in_bytes, None),
+                Signature::parse_der(sig_in_bytes),
+            ) else {
+                return false;
+            };
 
-                let mut pubkey_vec_in_string =
-                    ins.witness.clone().unwrap()[witness_len - 1].clone();
-                // print!("{:?}", tx);
-                let number_sign_req = pubkey_vec_in_string[0..2].to_string();
-                let number_sign_req = u32::from_str_radix(&number_sign_req, 16).unwrap();
-                if number_sign_req < 0x50 || number_sign_req > 0x60 {
-                    return false;
-                }
-                let number_sign_req = number_sign_req - 0x50;
-
-                if let Some(witness_script_asm) = &ins.inner_witnessscript_asm {
-                    let parts: Vec<&str> = witness_script_asm.split("OP_PUSHBYTES_33 ").collect();
-                    for i in 1..parts.len() {
-                        let pubkey_hex = parts[i].split_whitespace().next().unwrap();
-                        let pubkey_bytes = hex::decode(pubkey_hex).unwrap();
-                        let pubkey_in_bytes: [u8; 33] = pubkey_bytes.try_into().unwrap();
-                        pubkey_vec.push(pubkey_in_bytes);
-                    }
-                }
-                for pubkey in pubkey_vec.clone() {
-                    //pubkey in string
-                    let pubkey_in_string = hex::encode(pubkey.clone());
+            let hash = get_commitment_hash_legacy(
+                tx.clone().version,
+                tx.clone(),
+                index as u32, // index of the input , do this with a counter
+                sighash as u32,
+            );
 
-                    pubkey_hash_vec.push(sha256_hash(&pubkey.clone()));
-                }
+            let Ok(msg) = Message::parse_slice(&hash) else {
+                return false;
+            };
 
-                let mut total_ok: u32 = 0;
-
-                for sig in signatures_vector {
-                    let sign = Signature::parse_der(&sig).unwrap();
-
-                    for (counter, pubkey) in pubkey_vec.iter().enumerate() {
-                        let pubkey_hash = pubkey_hash_vec[counter].clone();
-
-                        let mut scriptcode: Vec<u8> = Vec::new();
-                        let redeem_script_str =
-                            ins.witness.clone().unwrap()[witness_len - 1].clone();
-                        let rs_vec = hex::decode(redeem_script_str).unwrap();
-                        let rs_size = rs_vec.len() as u64;
-                        let rs_size_in_varint = turn_to_varint(rs_size);
-                        scriptcode.extend_from_slice(&rs_size_in_varint);
-                        scriptcode.extend_from_slice(&rs_vec);
-
-                        let hash = get_commitment_hash_segwit(
-                            tx.clone(),
-                            tx.version,
-                            sighash_vector[&sig.clone()],
-                            tx.locktime,
-                            scriptcode,
-                            ins.sequence,
-                            ins.prevout.value,
-                            ins.txid.clone(),
-                            ins.vout,
-                        );
-
-                        let pubkey = PublicKey::parse_compressed(&pubkey).unwrap();
-                        let msg = Message::parse_slice(&hash).unwrap();
-
-                        let ret = verify(&msg, &sign, &pubkey);
-                        if ret {
-                            total_ok = total_ok + 1;
-                        }
-                    }
-                }
+            let ret = verify(&msg, &sign, &pubkey);
 
-                if total_ok < number_sign_req {
-                    return false;
-                }
+            if ret == false {
+                return false;
             }
-            "p2sh" => {
-                // has 2 nested case + a pure p2sh case
-
-                // if no witness , its pure p2sh
-                if ins.witness.is_none() {
-                    return false;
-                } else if ins.witness.as_ref().unwrap().len() == 2 {
-                    // nested p2wpkh
-
-                    let sign_in_witness = ins.witness.clone().unwrap()[0].clone();
-                    let sign_to_bytes = hex::decode(sign_in_witness).unwrap();
-
-                    let sign_to_verify = &sign_to_bytes[..sign_to_bytes.len() - 1];
-
-                    let pubkey = ins.witness.clone().unwrap()[1].clone();
-                    let pubkey_in_bytes_vec = hex::decode(pubkey).unwrap();
-                    let pubkey_in_bytes: [u8; 33] = pubkey_in_bytes_vec.clone().try_into().unwrap();
-
-                    // extract last byte of the sign
-                    let sighash = sign_to_bytes.last().cloned().unwrap();
-
-                    let mut scriptcode: Vec<u8> = Vec::new();
-                    scriptcode.push(0x19);
-                    scriptcode.push(0x76);
-                    scriptcode.push(0xa9);
-                    scriptcode.push(0x14);
-                    let pub_hash = hash160(&pubkey_in_bytes_vec);
-                    scriptcode.extend_from_slice(&pub_hash);
-                    scriptcode.push(0x88);
-                    scriptcode.push(0xac);
-
-                    let hash = get_commitment_hash_segwit(
-                        tx.clone(),
-                        tx.version,
-                        sighash as u32,
-                        tx.locktime,
-                        scriptcode,
-                        ins.sequence,
-                        ins.prevout.value,
-                        ins.txid.clone(),
-                        ins.vout,
-                    );
-
-                    // verification
-                    let signature = Signature::parse_der(&sign_to_verify).unwrap();
-                    let pubkey = PublicKey::parse_compressed(&pubkey_in_bytes).unwrap();
-                    let msg = Message::parse_slice(&hash).unwrap();
-
-                    let ret = verify(&msg, &signature, &pubkey);
-
-                    if ret == false {
-                        return false;
-                    }
-                } else {
-                    // nested p2wsh
-
-                    let witness_len = ins.witness.clone().unwrap().len();
-
-                    let mut signatures_vector: Vec<Vec<u8>> = Vec::new();
-                    let mut sighash_vector: HashMap<Vec<u8>, u32> = HashMap::new();
-                    let mut pubkey_vec: Vec<[u8; 33]> = Vec::new();
-                    let mut pubkey_hash_vec: Vec<Vec<u8>> = Vec::new();
-
-                    for i in 0..(witness_len - 1) {
-                        let witness_to_bytes =
-                            hex::decode(ins.witness.clone().unwrap()[i].clone()).unwrap();
-
-                        if witness_to_bytes.len() == 0 {
-                            continue;
-                        }
-
-                        let sign_to_verify =
-                            witness_to_bytes[..witness_to_bytes.len() - 1].to_vec();
-                        let sighash = witness_to_bytes.last().cloned().unwrap();
-
-                        signatures_vector.push(sign_to_verify.clone());
-                        sighash_vector.insert(sign_to_verify, sighash as u32);
-                    }
-
-                    let mut pubkey_vec_in_string =
-                        ins.witness.clone().unwrap()[witness_len - 1].clone();
-
-                    let number_sign_req = pubkey_vec_in_string[0..2].to_string();
-                    let number_sign_req = u32::from_str_radix(&number_sign_req, 16).unwrap();
-                    if number_sign_req < 0x50 || number_sign_req > 0x60 {
-                        return false;
-                    }
-                    let number_sign_req = number_sign_req - 0x50;
-
-                    if let Some(witness_script_asm) = &ins.inner_witnessscript_asm {
-                        let parts: Vec<&str> =
-                            witness_script_asm.split("OP_PUSHBYTES_33 ").collect();
-                        for i in 1..parts.len() {
-                            let pubkey_hex = parts[i].split_whitespace().next().unwrap(); // get the part before the next space
-                            let pubkey_bytes = hex::decode(pubkey_hex).unwrap(); // convert the hex string to bytes
-                            let pubkey_in_bytes: [u8; 33] = pubkey_bytes.try_into().unwrap();
-                            pubkey_vec.push(pubkey_in_bytes);
-                        }
-                    }
+        }
+        _ => {
+            return true;
+        }
+    }
 
-                    for pubkey in pubkey_vec.clone() {
-                        //pubkey in string
-                        let pubkey_in_string = hex::encode(pubkey.clone());
+    true
+}
 
-                        pubkey_hash_vec.push(sha256_hash(&pubkey.clone()));
-                    }
+// m-of-n OP_CHECKMULTISIG witness, the signatures first and the witness script last, shared
+// by native p2wsh and p2sh-p2wsh. a witness that doesn't parse is invalid rather than a panic
+fn check_multisig_witness(tx: &Transaction, ins: &Input, witness: &[String]) -> bool {
+    let Some((redeem_script_str, signatures)) = witness.split_last() else {
+        return false;
+    };
 
-                    let mut total_ok: u32 = 0;
-
-                    for sig in signatures_vector {
-                        let sign = Signature::parse_der(&sig).unwrap();
-
-                        for (counter, pubkey) in pubkey_vec.iter().enumerate() {
-                            let pubkey_hash = pubkey_hash_vec[counter].clone();
-
-                            let mut scriptcode: Vec<u8> = Vec::new();
-                            let redeem_script_str =
-                                ins.witness.clone().unwrap()[witness_len - 1].clone();
-                            let rs_vec = hex::decode(redeem_script_str).unwrap();
-                            let rs_size = rs_vec.len() as u64;
-                            let rs_size_in_varint = turn_to_varint(rs_size);
-                            scriptcode.extend_from_slice(&rs_size_in_varint);
-                            scriptcode.extend_from_slice(&rs_vec);
-
-                            let hash = get_commitment_hash_segwit(
-                                tx.clone(),
-                                tx.version,
-                                sighash_vector[&sig.clone()],
-                                tx.locktime,
-                                scriptcode,
-                                ins.sequence,
-                                ins.prevout.value,
-                                ins.txid.clone(),
-                                ins.vout,
-                            );
-
-                            let pubkey = PublicKey::parse_compressed(&pubkey).unwrap();
-                            let msg = Message::parse_slice(&hash).unwrap();
-
-                            let ret = verify(&msg, &sign, &pubkey);
-                            if ret {
-                                total_ok = total_ok + 1;
-                            }
-                        }
-                    }
+    let mut signatures_vector: Vec<Vec<u8>> = Vec::new();
+    let mut sighash_vector: HashMap<Vec<u8>, u32> = HashMap::new();
+    for item in signatures {
+        let Ok(witness_to_bytes) = hex::decode(item) else {
+            return false;
+        };
+        let Some((&sighash, sign_to_verify)) = witness_to_bytes.split_last() else {
+            continue;
+        };
+        signatures_vector.push(sign_to_verify.to_vec());
+        sighash_vector.insert(sign_to_verify.to_vec(), sighash as u32);
+    }
 
-                    if total_ok < number_sign_req {
-                        return false;
-                    }
-                }
-            }
-            "p2pkh" => {
-                let sig_len_hex = &ins.scriptsig[..2];
-                let sig_len_bytes = hex::decode(sig_len_hex).unwrap();
-                let convert_to_dec = u8::from_be_bytes(sig_len_bytes.try_into().unwrap()) as usize;
-
-                // Take convert_to_dec bytes (2*convert_to_dec characters) after the first byte
-                let sig_w_sighash = &ins.scriptsig[2..(2 + 2 * convert_to_dec)];
-                let sighash = &sig_w_sighash.clone()[(2 * convert_to_dec - 2)..];
-                let sighash = u8::from_str_radix(sighash, 16).unwrap();
-                let sig = &sig_w_sighash.clone()[..(2 * convert_to_dec - 2)];
-
-                // Take the rest of the string
-                let pubkey_str = &ins.scriptsig[((2 + 2 * convert_to_dec) + 2)..];
-                let mut pubkey_in_bytes: Vec<u8> = hex::decode(pubkey_str).unwrap();
-                // If the public key is 65 bytes long, compress it
-                if pubkey_in_bytes.len() == 65 {
-                    pubkey_in_bytes = compress_pubkey(&pubkey_in_bytes);
-                }
-                let pubkey_in_bytes: [u8; 33] = pubkey_in_bytes.try_into().expect(&format!(
-                    "Failed to convert public key to bytes for transaction: {:?}",
-                    pubkey_str
-                ));
-                let pubkey = PublicKey::parse_compressed(&pubkey_in_bytes.clone()).unwrap();
+    let Ok(rs_vec) = hex::decode(redeem_script_str) else {
+        return false;
+    };
+    let Some(&number_sign_req) = rs_vec.first() else {
+        return false;
+    };
+    if !(0x50..=0x60).contains(&number_sign_req) {
+        return false;
+    }
+    let number_sign_req = (number_sign_req - 0x50) as u32;
 
-                let sig_in_bytes = hex::decode(sig).unwrap();
-                let sign = Signature::parse_der(&sig_in_bytes).unwrap();
+    // pubkeys come from the witness script itself, not the asm shipped alongside it
+    let pubkey_vec: Vec<[u8; 33]> = pushed_data(&rs_vec)
+        .iter()
+        .filter_map(|data| <[u8; 33]>::try_from(data.as_slice()).ok())
+        .collect();
 
-                let hash = get_commitment_hash_legacy(
-                    tx.clone().version,
-                    tx.clone(),
-                    index as u32, // index of the input , do this with a counter
-                    sighash as u32,
-                );
+    let mut scriptcode: Vec<u8> = Vec::new();
+    scriptcode.extend_from_slice(&turn_to_varint(rs_vec.len() as u64));
+    scriptcode.extend_from_slice(&rs_vec);
 
-                let msg = Message::parse_slice(&hash).unwrap();
+    let mut total_ok: u32 = 0;
+    for sig in signatures_vector {
+        let Ok(sign) = Signature::parse_der(&sig) else {
+            return false;
+        };
 
-                let ret = verify(&msg, &sign, &pubkey);
+        let hash = get_commitment_hash_segwit(
+            tx.clone(),
+            tx.version,
+            sighash_vector[&sig],
+            tx.locktime,
+            scriptcode.clone(),
+            ins.sequence,
+            ins.prevout.value,
+            ins.txid.clone(),
+            ins.vout,
+        );
+        let Ok(msg) = Message::parse_slice(&hash) else {
+            return false;
+        };
 
-                if ret == false {
-                    return false;
-                }
-            }
-            _ => {
-                continue;
+        for pubkey in &pubkey_vec {
+            let Ok(pubkey) = PublicKey::parse_compressed(pubkey) else {
+                return false;
+            };
+            if verify(&msg, &sign, &pubkey) {
+                total_ok += 1;
             }
         }
     }
 
-    return true;
+    total_ok >= number_sign_req
 }
 
 pub fn find_pure_p2sh() {
@@ -907,21 +1733,21 @@ fn get_txid(version: u32, inputs: Vec<Vec<u8>>, outputs: Vec<Vec<u8>>, locktime:
     tx_array
 }
 
-pub fn collect_txids(tx: Transaction) -> String {
+pub fn collect_txids(tx: &Transaction) -> String {
     let mut input_vecs: Vec<Vec<u8>> = Vec::new();
     let mut output_vecs: Vec<Vec<u8>> = Vec::new();
 
-    for ins in tx.vin {
+    for ins in &tx.vin {
         let mut input: Vec<u8> = Vec::new();
 
         // add outpoint
-        let txid = hex::decode(ins.txid).unwrap();
+        let txid = hex::decode(&ins.txid).unwrap();
         let reversed_txid: Vec<u8> = txid.iter().rev().cloned().collect();
         input.extend_from_slice(&reversed_txid);
         input.extend_from_slice(&ins.vout.to_le_bytes());
 
         // add scriptSig
-        let scriptSig = hex::decode(ins.scriptsig).unwrap();
+        let scriptSig = hex::decode(&ins.scriptsig).unwrap();
         let scriptSig_size = scriptSig.len() as u64;
         let scriptsig_size_in_varint = turn_to_varint(scriptSig_size);
         input.extend_from_slice(&scriptsig_size_in_varint);
@@ -933,7 +1759,7 @@ pub fn collect_txids(tx: Transaction) -> String {
         input_vecs.push(input);
     }
 
-    for outs in tx.vout {
+    for outs in &tx.vout {
         let mut output: Vec<u8> = Vec::new();
 
         // add value
@@ -941,7 +1767,7 @@ pub fn collect_txids(tx: Transaction) -> String {
         output.extend_from_slice(&value);
 
         // add scriptPubKey
-        let scriptPubKey = hex::decode(outs.scriptpubkey).unwrap();
+        let scriptPubKey = hex::decode(&outs.scriptpubkey).unwrap();
         let scriptPubKey_size = scriptPubKey.len() as u64;
         let scriptPubKey_size_in_varint = turn_to_varint(scriptPubKey_size);
         output.extend_from_slice(&scriptPubKey_size_in_varint);
@@ -1156,22 +1982,22 @@ fn get_commitment_hash_legacy(
     commitment_hash
 }
 
-pub fn test_weight(tx: Transaction) -> u64 {
+pub fn test_weight(tx: &Transaction) -> u64 {
     let mut input_vecs: Vec<Vec<u8>> = Vec::new();
     let mut output_vecs: Vec<Vec<u8>> = Vec::new();
     let mut witness_vecs: Vec<Vec<u8>> = Vec::new();
 
-    for ins in tx.vin.clone() {
+    for ins in &tx.vin {
         let mut input: Vec<u8> = Vec::new();
 
         // add outpoint
-        let txid = hex::decode(ins.txid).unwrap();
+        let txid = hex::decode(&ins.txid).unwrap();
         let reversed_txid: Vec<u8> = txid.iter().rev().cloned().collect();
         input.extend_from_slice(&reversed_txid);
         input.extend_from_slice(&ins.vout.to_le_bytes());
 
         // add scriptSig
-        let scriptSig = hex::decode(ins.scriptsig).unwrap();
+        let scriptSig = hex::decode(&ins.scriptsig).unwrap();
         let scriptSig_size = scriptSig.len() as u64;
         let scriptsig_size_in_varint = turn_to_varint(scriptSig_size);
         input.extend_from_slice(&scriptsig_size_in_varint);
@@ -1183,7 +2009,7 @@ pub fn test_weight(tx: Transaction) -> u64 {
         input_vecs.push(input);
     }
 
-    for outs in tx.vout.clone() {
+    for outs in &tx.vout {
         let mut output: Vec<u8> = Vec::new();
 
         // add value
@@ -1191,7 +2017,7 @@ pub fn test_weight(tx: Transaction) -> u64 {
         output.extend_from_slice(&value);
 
         // add scriptPubKey
-        let scriptPubKey = hex::decode(outs.scriptpubkey).unwrap();
+        let scriptPubKey = hex::decode(&outs.scriptpubkey).unwrap();
         let scriptPubKey_size = scriptPubKey.len() as u64;
         let scriptPubKey_size_in_varint = turn_to_varint(scriptPubKey_size);
         output.extend_from_slice(&scriptPubKey_size_in_varint);
@@ -1200,10 +2026,10 @@ pub fn test_weight(tx: Transaction) -> u64 {
         output_vecs.push(output);
     }
 
-    for ins in tx.vin.clone() {
+    for ins in &tx.vin {
         let mut witness_vec: Vec<u8> = Vec::new();
 
-        if let Some(witness) = ins.witness {
+        if let Some(witness) = &ins.witness {
             let witness_len = witness.len() as u64;
             let witness_len_in_varint = turn_to_varint(witness_len);
             witness_vec.extend_from_slice(&witness_len_in_varint);
@@ -1221,14 +2047,14 @@ pub fn test_weight(tx: Transaction) -> u64 {
     }
 
     let (witness_data, non_witness_data) =
-        divide_and_conquer(tx.clone(), input_vecs, output_vecs, witness_vecs);
+        divide_and_conquer(tx, input_vecs, output_vecs, witness_vecs);
 
     let new_wt = correct_cal_weight(non_witness_data, witness_data);
     new_wt
 }
 
 fn divide_and_conquer(
-    tx: Transaction,
+    tx: &Transaction,
     inputs: Vec<Vec<u8>>,
     outputs: Vec<Vec<u8>>,
     witnesses: Vec<Vec<u8>>,
@@ -1875,7 +2701,7 @@ fn get_merkle_root_wtxid(wtxids: &[Vec<u8>]) -> Vec<u8> {
     merkle_root_vec
 }
 
-fn get_wtxid(tx: Transaction) -> Vec<u8> {
+fn get_wtxid(tx: &Transaction) -> Vec<u8> {
     let mut input_vecs: Vec<Vec<u8>> = Vec::new();
     let mut output_vecs: Vec<Vec<u8>> = Vec::new();
     let mut witness_vecs: Vec<Vec<u8>> = Vec::new();
@@ -1883,23 +2709,24 @@ fn get_wtxid(tx: Transaction) -> Vec<u8> {
     let mut total: u32 = 0;
     let mut non_segwit: u32 = 0;
 
-    for ins in tx.vin.clone() {
+    for ins in &tx.vin {
         let mut input: Vec<u8> = Vec::new();
 
         total = total + 1;
 
-        if ins.prevout.scriptpubkey_type == "p2pkh" {
+        // without any witness the wtxid is just the txid
+        if ins.witness.is_none() {
             non_segwit = non_segwit + 1;
         }
 
         // add outpoint
-        let txid = hex::decode(ins.txid).unwrap();
+        let txid = hex::decode(&ins.txid).unwrap();
         let reversed_txid: Vec<u8> = txid.iter().rev().cloned().collect();
         input.extend_from_slice(&reversed_txid);
         input.extend_from_slice(&ins.vout.to_le_bytes());
 
         // add scriptSig
-        let scriptSig = hex::decode(ins.scriptsig).unwrap();
+        let scriptSig = hex::decode(&ins.scriptsig).unwrap();
         let scriptSig_size = scriptSig.len() as u64;
         let scriptsig_size_in_varint = turn_to_varint(scriptSig_size);
         input.extend_from_slice(&scriptsig_size_in_varint);
@@ -1911,7 +2738,7 @@ fn get_wtxid(tx: Transaction) -> Vec<u8> {
         input_vecs.push(input);
     }
 
-    for outs in tx.vout.clone() {
+    for outs in &tx.vout {
         let mut output: Vec<u8> = Vec::new();
 
         // add value
@@ -1919,7 +2746,7 @@ fn get_wtxid(tx: Transaction) -> Vec<u8> {
         output.extend_from_slice(&value);
 
         // add scriptPubKey
-        let scriptPubKey = hex::decode(outs.scriptpubkey).unwrap();
+        let scriptPubKey = hex::decode(&outs.scriptpubkey).unwrap();
         let scriptPubKey_size = scriptPubKey.len() as u64;
         let scriptPubKey_size_in_varint = turn_to_varint(scriptPubKey_size);
         output.extend_from_slice(&scriptPubKey_size_in_varint);
@@ -1933,10 +2760,10 @@ fn get_wtxid(tx: Transaction) -> Vec<u8> {
         return txid.to_vec().iter().rev().cloned().collect();
     }
 
-    for ins in tx.vin.clone() {
+    for ins in &tx.vin {
         let mut witness_vec: Vec<u8> = Vec::new();
 
-        if let Some(witness) = ins.witness.clone() {
+        if let Some(witness) = &ins.witness {
             let witness_len = witness.len() as u64;
             let witness_len_in_varint = turn_to_varint(witness_len);
             witness_vec.extend_from_slice(&witness_len_in_varint);
@@ -1975,6 +2802,223 @@ fn get_wtxid(tx: Transaction) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Clock;
+
+    #[test]
+    fn test_block_merkle_root_refuses_duplicates() {
+        let txids: Vec<String> = ["aa", "bb", "cc"].iter().map(|b| b.repeat(32)).collect();
+        let root = block_merkle_root(&txids).unwrap();
+        assert_eq!(root, get_merkle_root(txids.clone()));
+
+        let mut duplicated = txids.clone();
+        duplicated.push(txids[2].clone());
+        assert_eq!(get_merkle_root(duplicated.clone()), root);
+        assert!(block_merkle_root(&duplicated).is_err());
+    }
+
+    // the block a fixed clock mines over `mempool_dir` has to match `golden` byte for byte,
+    // UPDATE_GOLDEN=1 rewrites the file instead
+    fn check_golden(mempool_dir: &str, golden: &str) {
+        check_golden_with(
+            Config {
+                mempool_dir: mempool_dir.to_string(),
+                ..Config::default()
+            },
+            golden,
+        );
+    }
+
+    fn check_golden_with(config: Config, golden: &str) {
+        let config = Config {
+            clock: Clock::Fixed(1713000000),
+            ..config
+        };
+        let block = mine_block(&config).unwrap().0.join("\n");
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(golden, &block).unwrap();
+        }
+        assert_eq!(block, fs::read_to_string(golden).unwrap());
+    }
+
+    #[test]
+    fn test_parent_problems() {
+        let [a, b, c] = ["aa", "bb", "cc"].map(|b| b.repeat(32));
+        let parents: HashMap<&str, Vec<&str>> = HashMap::from([
+            (b.as_str(), vec![a.as_str(), a.as_str()]),
+            (c.as_str(), vec![b.as_str()]),
+        ]);
+
+        let ordered = vec![a.clone(), b.clone(), c.clone()];
+        assert!(parent_problems(&ordered, &parents).is_empty());
+
+        let problems = parent_problems(&[b.clone(), a.clone(), c.clone()], &parents);
+        assert_eq!(
+            problems,
+            vec![format!("{} spends {}, which comes after it", b, a)]
+        );
+
+        let problems = parent_problems(&[a.clone(), c.clone()], &parents);
+        assert_eq!(
+            problems,
+            vec![format!("{} spends {}, which isn't in the block", c, b)]
+        );
+    }
+
+    #[test]
+    fn test_golden_block_small() {
+        check_golden("../mempool_test", "../golden/mempool_test.txt");
+    }
+
+    // a slice of ../mempool small enough for every run: parent chains up to six deep, every
+    // input type the full one has, two transactions that aren't final yet and one spending
+    // an output of a third
+    #[test]
+    fn test_golden_block_chains() {
+        for (algorithm, max_weight, golden) in [
+            (
+                SelectionAlgorithm::Greedy,
+                4000000,
+                "../golden/mempool_golden.txt",
+            ),
+            (
+                SelectionAlgorithm::AncestorFeerate,
+                4000000,
+                "../golden/mempool_golden_ancestor.txt",
+            ),
+            (
+                SelectionAlgorithm::Greedy,
+                40000,
+                "../golden/mempool_golden_40k.txt",
+            ),
+            (
+                SelectionAlgorithm::AncestorFeerate,
+                40000,
+                "../golden/mempool_golden_ancestor_40k.txt",
+            ),
+        ] {
+            let config = Config {
+                mempool_dir: "../mempool_golden".to_string(),
+                algorithm,
+                max_weight,
+                ..Config::default()
+            };
+            check_golden_with(config, golden);
+        }
+    }
+
+    // takes minutes in a debug build: cargo test --release -- --ignored
+    #[test]
+    #[ignore]
+    fn test_golden_block() {
+        check_golden("../mempool", "../golden/mempool.txt");
+    }
+
+    #[test]
+    fn test_check_sig_consults_cache() {
+        let entry = fs::read_dir("../mempool_test").unwrap().next().unwrap();
+        let mut tx: Transaction =
+            serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap();
+
+        let mut sig_cache = SigCache::default();
+        assert!(check_sig(tx.clone(), &mut sig_cache));
+        assert_eq!(sig_cache.hits, 0);
+
+        assert!(check_sig(tx.clone(), &mut sig_cache));
+        assert_eq!(sig_cache.hits, 1);
+
+        // the p2wpkh sighash commits to the amount, which the wtxid doesn't, the cached
+        // entry must not let a tampered prevout through
+        tx.vin[0].prevout.value += 1;
+        assert!(!check_sig(tx.clone(), &mut sig_cache));
+        assert_eq!(sig_cache.hits, 1);
+
+        tx.vin[0].prevout.value -= 1;
+        // nor is the entry used for a different scriptPubKey
+        tx.vin[0].prevout.scriptpubkey = format!("0014{}", "00".repeat(20));
+        check_sig(tx, &mut sig_cache);
+        assert_eq!(sig_cache.hits, 1);
+    }
+
+    #[test]
+    fn test_witness_commitment() {
+        let wtxids = vec![vec![0u8; 32], vec![7u8; 32]];
+        let reserved = [5u8; 32];
+        let extranonce = default_extranonce();
+
+        let parts = get_coinbase_transaction(
+            840000,
+            0,
+            5000000000,
+            Some(get_merkle_root_wtxid(&wtxids)),
+            reserved,
+        );
+        let coinbase =
+            rawtx::decode_raw_transaction(&hex::decode(parts.assemble(&extranonce)).unwrap())
+                .unwrap();
+        assert_eq!(coinbase.vout.len(), 2);
+        assert_eq!(coinbase.vin[0].witness, Some(vec![hex::encode(reserved)]));
+        assert!(check_witness_commitment(&coinbase, &wtxids, true).is_ok());
+        assert!(check_witness_commitment(&coinbase, &[vec![0u8; 32]], false).is_err());
+
+        // a later output that looks like a commitment is the one that counts
+        let mut shadowed = coinbase.clone();
+        let mut bogus = shadowed.vout[1].clone();
+        bogus.scriptpubkey = format!("6a24aa21a9ed{}", "11".repeat(32));
+        shadowed.vout.push(bogus);
+        assert!(check_witness_commitment(&shadowed, &wtxids, true).is_err());
+
+        // no witness data, no commitment and a legacy serialized coinbase
+        let parts = get_coinbase_transaction(840000, 0, 5000000000, None, reserved);
+        let raw = hex::decode(parts.assemble(&extranonce)).unwrap();
+        assert_eq!(raw_txid(&raw).unwrap(), {
+            let mut txid = sha256_hash(&sha256_hash(&raw));
+            txid.reverse();
+            hex::encode(txid)
+        });
+        let coinbase = rawtx::decode_raw_transaction(&raw).unwrap();
+        assert_eq!(coinbase.vout.len(), 1);
+        assert!(coinbase.vin[0].witness.is_none());
+        assert!(check_witness_commitment(&coinbase, &wtxids[..1], false).is_ok());
+        assert!(check_witness_commitment(&coinbase, &wtxids, true).is_err());
+    }
+
+    // the mempool_test transaction's ids and the commitment of a block holding just it,
+    // worked out apart from this code
+    #[test]
+    fn test_witness_commitment_pinned() {
+        let entry = fs::read_dir("../mempool_test").unwrap().next().unwrap();
+        let tx: Transaction =
+            serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap();
+        let txid = "698675daa46546737422856fe2eb81fcb4740eb19d1f4cf7837c27603c9f9ce7";
+        let wtxid = "9896881a580f7df4798dabd3b1b5d2720f1e5b9eecf3d580f98ece6a67f960f9";
+        assert_eq!(collect_txids(&tx), txid);
+        assert_eq!(hex::encode(get_wtxid(&tx)), wtxid);
+
+        // without witness data the wtxid is the txid, whatever the inputs are labelled
+        let mut stripped = tx.clone();
+        for ins in &mut stripped.vin {
+            ins.witness = None;
+        }
+        assert_eq!(hex::encode(get_wtxid(&stripped)), collect_txids(&stripped));
+
+        // every selected transaction is in the witness root, the first one too
+        let config = Config {
+            mempool_dir: "../mempool_test".to_string(),
+            clock: Clock::Fixed(1713000000),
+            ..Config::default()
+        };
+        let (template, _) = build_template(&config).unwrap();
+        assert_eq!(
+            template.wtxids,
+            vec![vec![0u8; 32], hex::decode(wtxid).unwrap()]
+        );
+        let coinbase =
+            rawtx::decode_raw_transaction(&hex::decode(&template.coinbase).unwrap()).unwrap();
+        assert_eq!(
+            coinbase.vout[1].scriptpubkey,
+            "6a24aa21a9edc3b26e844e48c90c75fd2ed2ed53f2ababb35dc7e7efc44154094298f4ecc4f8"
+        );
+    }
 
     #[test]
     fn test_get_merkle_root() {
@@ -2013,7 +3057,7 @@ fn test_get_wtxid() {
         let tx: Transaction =
             serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap();
 
-        let wtxid = get_wtxid(tx);
+        let wtxid = get_wtxid(&tx);
 
         let expected_wtxid = hex::encode(wtxid);
         assert_eq!( expected_wtxid , "0200000000010117829ba9d14d441742d8d3f692efb3629742d5bf239a7017e3135be0b2c27996010000000000000000012202000000000000225120ea6e164912a1bd91f6b8652826dc3fa780c93f1a789e7a8a1ff61dfdce2801560341a0b18a9af707e1548028a8e86608ec2b40055573b6dc6a6bd70a6eb9009cfa85c5160f151567f09a8f6fe4f0571135c2bf2a76aaa2ebde5d6684bd06a3f1002701fd8a0251690063036f7264010117746578742f68746d6c3b636861727365743d7574662d38004d08023c73637269707420646174612d733d2230786130313937336161653638653261613639383761323131306430336366323639383863343732663036393338303533653531373865343565626634386566643622207372633d222f636f6e74656e742f663830623933343636613238633565666337303366616230326265656262663465333265316263346630363361633237666564666437396164393832663263656930223e3c2f7363726970743e3c626f6479207374796c653d22646973706c61793a206e6f6e65223e3c2f626f64793e000000000000000062766d76341bc301f867404fd30ee425db8e71603b2d0eb7c070bec1090002971c3336bec9ab0687d9589e16c599eb2df8f4d7b67f020010f311150465150a9e16f4008209664d52fe819620492d030a414224b3f175b88af0b71b6b73a7b5eb773d80660985578b86270197ae295a847d506d5d01b819eb2730c6010b0932ca4c46a200a77da942914fb6ef426400d4d5879a245f8017212fc212dcaafa41c9f583788fc231e1fd3d21ab3113f9424b55d851125d3c585386903684a278db8185f2c5ecf1cb9c9c52ca90d763b0a1f9f5347310793057ecc6b9adbc3f0b61c3002dfc407ac991d98e986247edad4174dc88e2de14fd5e0b0395644181e20d833feaf8a4b228da700249de6ef7b1f1bc538e8fb6bc8e33352a92df7009d850b41e726517f0abed5a1c8ceea6ff0f4c591fd5abce654cc446478658b9389ddde4497c80a9a1b6e0e6866e04f2b6ca48549b41e3d54d286b9493e22bcc63c8ee01f30f346d06db5680dc067df1f44ec6036bd3bebfbd63c47b053baa0ed9311d94cab7403d69ebd506006821c0d2d8084d959566f0788913a3b8c3a8cbf9a458d9b379839a380bd9b0aa8f57ce00000000" );
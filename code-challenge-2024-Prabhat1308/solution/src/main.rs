@@ -1,5 +1,37 @@
-use solution::run;
+use solution::cli::{parse_args, Command};
+use std::process;
 
 fn main() {
-    run();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let (command, config) = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
+
+    let result = match command {
+        Command::Mine => solution::mine(&config),
+        Command::Template => solution::template(&config),
+        Command::Validate => solution::validate(&config),
+        Command::InspectTx(path) => solution::inspect_tx(&path, &config),
+        Command::Prove(txids) => solution::prove(&txids, &config),
+        Command::CheckBlock => solution::check_block(&config),
+        Command::Compact => solution::compact(&config),
+        Command::Reconstruct(path) => solution::reconstruct(&path, &config),
+        Command::StratumJob => solution::stratum_job(&config),
+        Command::SubmitShare {
+            job_file,
+            extranonce2,
+            ntime,
+            nonce,
+        } => solution::submit_share(&job_file, &extranonce2, &ntime, &nonce, &config),
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,331 @@
+use crate::{sha256_hash, turn_to_varint};
+
+// all hashes in here are in internal byte order, the reverse of how txids are displayed
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut combined = Vec::with_capacity(64);
+    combined.extend_from_slice(left);
+    combined.extend_from_slice(right);
+    sha256_hash(&sha256_hash(&combined)).try_into().unwrap()
+}
+
+// a displayed txid as the bytes that go into the tree
+pub fn txid_to_bytes(txid: &str) -> Result<[u8; 32], String> {
+    let mut bytes = hex::decode(txid).map_err(|e| format!("{}: {}", txid, e))?;
+    bytes.reverse();
+    bytes
+        .try_into()
+        .map_err(|_| format!("{}: not 32 bytes", txid))
+}
+
+// one level up, an odd last element is paired with itself
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+        .collect()
+}
+
+pub fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    compute_merkle_root(leaves).0
+}
+
+// the root and whether the tree is mutated, like ComputeMerkleRoot in Bitcoin Core: two equal
+// siblings anywhere mean another list of leaves, with the last ones duplicated or left out,
+// has the same root (CVE-2012-2459)
+pub fn compute_merkle_root(leaves: &[[u8; 32]]) -> ([u8; 32], bool) {
+    if leaves.is_empty() {
+        return ([0u8; 32], false);
+    }
+    let mut mutated = false;
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        mutated |= level
+            .chunks(2)
+            .any(|pair| pair.len() == 2 && pair[0] == pair[1]);
+        level = next_level(&level);
+    }
+    (level[0], mutated)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleBranch {
+    // position of the leaf in the block, its bits tell on which side each sibling goes
+    pub index: u32,
+    // siblings from the leaf level up to just below the root
+    pub hashes: Vec<[u8; 32]>,
+}
+
+pub fn merkle_branch(leaves: &[[u8; 32]], index: usize) -> Option<MerkleBranch> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut hashes = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut position = index;
+    while level.len() > 1 {
+        let sibling = (position ^ 1).min(level.len() - 1);
+        hashes.push(level[sibling]);
+        level = next_level(&level);
+        position /= 2;
+    }
+
+    Some(MerkleBranch {
+        index: index as u32,
+        hashes,
+    })
+}
+
+pub fn root_from_branch(leaf: &[u8; 32], branch: &MerkleBranch) -> [u8; 32] {
+    let mut hash = *leaf;
+    for (depth, sibling) in branch.hashes.iter().enumerate() {
+        hash = if (branch.index >> depth) & 1 == 1 {
+            hash_pair(sibling, &hash)
+        } else {
+            hash_pair(&hash, sibling)
+        };
+    }
+    hash
+}
+
+// checks the branch against the merkle root committed to in an 80 byte block header
+pub fn verify_branch(header: &[u8], leaf: &[u8; 32], branch: &MerkleBranch) -> bool {
+    header.len() == 80 && root_from_branch(leaf, branch)[..] == header[36..68]
+}
+
+// matched leaves with their position in the block
+pub type Matches = Vec<(u32, [u8; 32])>;
+
+// BIP37 partial merkle tree, as carried by the merkleblock message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialMerkleTree {
+    pub total: u32,
+    pub hashes: Vec<[u8; 32]>,
+    pub flags: Vec<bool>,
+}
+
+// number of nodes at `height` above the leaves
+fn width(total: usize, height: u32) -> usize {
+    (total + (1 << height) - 1) >> height
+}
+
+fn tree_height(total: usize) -> u32 {
+    let mut height = 0;
+    while width(total, height) > 1 {
+        height += 1;
+    }
+    height
+}
+
+fn node_hash(leaves: &[[u8; 32]], height: u32, pos: usize) -> [u8; 32] {
+    if height == 0 {
+        return leaves[pos];
+    }
+    let left = node_hash(leaves, height - 1, pos * 2);
+    let right = if pos * 2 + 1 < width(leaves.len(), height - 1) {
+        node_hash(leaves, height - 1, pos * 2 + 1)
+    } else {
+        left
+    };
+    hash_pair(&left, &right)
+}
+
+impl PartialMerkleTree {
+    // tree proving the leaves flagged in `matches`
+    pub fn build(leaves: &[[u8; 32]], matches: &[bool]) -> Self {
+        assert_eq!(leaves.len(), matches.len());
+        let mut tree = PartialMerkleTree {
+            total: leaves.len() as u32,
+            hashes: Vec::new(),
+            flags: Vec::new(),
+        };
+        if !leaves.is_empty() {
+            tree.traverse_and_build(leaves, matches, tree_height(leaves.len()), 0);
+        }
+        tree
+    }
+
+    fn traverse_and_build(
+        &mut self,
+        leaves: &[[u8; 32]],
+        matches: &[bool],
+        height: u32,
+        pos: usize,
+    ) {
+        // does this node have a matched leaf below it
+        let start = pos << height;
+        let end = ((pos + 1) << height).min(leaves.len());
+        let parent_of_match = matches[start..end].iter().any(|m| *m);
+        self.flags.push(parent_of_match);
+
+        if height == 0 || !parent_of_match {
+            self.hashes.push(node_hash(leaves, height, pos));
+        } else {
+            self.traverse_and_build(leaves, matches, height - 1, pos * 2);
+            if pos * 2 + 1 < width(leaves.len(), height - 1) {
+                self.traverse_and_build(leaves, matches, height - 1, pos * 2 + 1);
+            }
+        }
+    }
+
+    // the root and the matched leaves with their positions, errors on malformed trees
+    pub fn extract_matches(&self) -> Result<([u8; 32], Matches), String> {
+        let total = self.total as usize;
+        if total == 0 {
+            return Err("empty tree".to_string());
+        }
+        if self.hashes.len() > total {
+            return Err("more hashes than transactions".to_string());
+        }
+        if self.flags.len() < self.hashes.len() {
+            return Err("fewer flags than hashes".to_string());
+        }
+
+        let mut cursor = (0, 0);
+        let mut matches = Vec::new();
+        let root = self.traverse_and_extract(tree_height(total), 0, &mut cursor, &mut matches)?;
+
+        // every flag byte and hash has to be used up
+        if cursor.0.div_ceil(8) != self.flags.len().div_ceil(8) || cursor.1 != self.hashes.len() {
+            return Err("unused flags or hashes".to_string());
+        }
+        Ok((root, matches))
+    }
+
+    fn traverse_and_extract(
+        &self,
+        height: u32,
+        pos: usize,
+        cursor: &mut (usize, usize),
+        matches: &mut Matches,
+    ) -> Result<[u8; 32], String> {
+        let flag = *self.flags.get(cursor.0).ok_or("ran out of flags")?;
+        cursor.0 += 1;
+
+        if height == 0 || !flag {
+            let hash = *self.hashes.get(cursor.1).ok_or("ran out of hashes")?;
+            cursor.1 += 1;
+            if height == 0 && flag {
+                matches.push((pos as u32, hash));
+            }
+            return Ok(hash);
+        }
+
+        let left = self.traverse_and_extract(height - 1, pos * 2, cursor, matches)?;
+        let right = if pos * 2 + 1 < width(self.total as usize, height - 1) {
+            let right = self.traverse_and_extract(height - 1, pos * 2 + 1, cursor, matches)?;
+            if right == left {
+                return Err("identical left and right branches".to_string());
+            }
+            right
+        } else {
+            left
+        };
+        Ok(hash_pair(&left, &right))
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&self.total.to_le_bytes());
+        raw.extend_from_slice(&turn_to_varint(self.hashes.len() as u64));
+        for hash in &self.hashes {
+            raw.extend_from_slice(hash);
+        }
+
+        // flags are packed least significant bit first
+        let mut flag_bytes = vec![0u8; self.flags.len().div_ceil(8)];
+        for (i, flag) in self.flags.iter().enumerate() {
+            if *flag {
+                flag_bytes[i / 8] |= 1 << (i % 8);
+            }
+        }
+        raw.extend_from_slice(&turn_to_varint(flag_bytes.len() as u64));
+        raw.extend_from_slice(&flag_bytes);
+        raw
+    }
+}
+
+// payload of the merkleblock message: the header followed by the partial tree
+pub fn merkle_block(header: &[u8], tree: &PartialMerkleTree) -> Vec<u8> {
+    let mut raw = header.to_vec();
+    raw.extend_from_slice(&tree.serialize());
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: u8) -> Vec<[u8; 32]> {
+        (0..n)
+            .map(|i| sha256_hash(&[i]).try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_merkle_branch_roundtrip() {
+        for n in 1..12 {
+            let leaves = leaves(n);
+            let root = merkle_root(&leaves);
+
+            let mut header = vec![0u8; 80];
+            header[36..68].copy_from_slice(&root);
+
+            for (i, leaf) in leaves.iter().enumerate() {
+                let branch = merkle_branch(&leaves, i).unwrap();
+                assert_eq!(root_from_branch(leaf, &branch), root);
+                assert!(verify_branch(&header, leaf, &branch));
+                assert!(!verify_branch(&header, &[0u8; 32], &branch));
+            }
+            assert!(merkle_branch(&leaves, n as usize).is_none());
+        }
+    }
+
+    #[test]
+    fn test_mutated_merkle_root() {
+        let honest = leaves(3);
+        let (root, mutated) = compute_merkle_root(&honest);
+        assert!(!mutated);
+
+        // [a, b, c] and [a, b, c, c] share a root, only the second is flagged
+        let mut duplicated = honest.clone();
+        duplicated.push(honest[2]);
+        assert_eq!(compute_merkle_root(&duplicated), (root, true));
+
+        // six leaves whose last pair repeats one level up
+        let six = leaves(6);
+        let mut padded = six.clone();
+        padded.extend_from_slice(&six[4..6]);
+        assert_eq!(compute_merkle_root(&padded).0, compute_merkle_root(&six).0);
+        assert!(compute_merkle_root(&padded).1);
+        assert!(!compute_merkle_root(&six).1);
+    }
+
+    #[test]
+    fn test_partial_merkle_tree() {
+        for n in 1..12 {
+            let leaves = leaves(n);
+            let root = merkle_root(&leaves);
+
+            for pick in 0..n as usize {
+                let matches: Vec<bool> = (0..n as usize).map(|i| i == pick || i % 5 == 3).collect();
+                let tree = PartialMerkleTree::build(&leaves, &matches);
+
+                let (extracted_root, found) = tree.extract_matches().unwrap();
+                assert_eq!(extracted_root, root);
+                let expected: Matches = (0..n as usize)
+                    .filter(|i| matches[*i])
+                    .map(|i| (i as u32, leaves[i]))
+                    .collect();
+                assert_eq!(found, expected);
+            }
+        }
+
+        // a single transaction tree is just the root hash and one set flag
+        let tree = PartialMerkleTree::build(&leaves(1), &[true]);
+        let raw = tree.serialize();
+        assert_eq!(raw.len(), 4 + 1 + 32 + 1 + 1);
+        assert_eq!(raw[raw.len() - 1], 0x01);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::script::{classify, disassemble};
+use crate::{sha256_hash, turn_to_varint, Input, Output, PrevOut, Transaction};
+
+// bytes of a serialized transaction (or message) being read front to back
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.bytes.len() {
+            return Err(format!(
+                "unexpected end of transaction at byte {}",
+                self.pos
+            ));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64, String> {
+        match self.u8()? {
+            0xfd => Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as u64),
+            0xfe => Ok(self.u32()? as u64),
+            0xff => self.u64(),
+            n => Ok(n as u64),
+        }
+    }
+
+    fn var_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.varint()? as usize;
+        self.take(len)
+    }
+}
+
+// the raw format carries no prevouts, so these come back as placeholders with a zero value
+pub(crate) fn decode_raw_transaction(raw: &[u8]) -> Result<Transaction, String> {
+    let mut reader = Reader::new(raw);
+    let tx = read_transaction(&mut reader)?;
+    if reader.pos != raw.len() {
+        return Err(format!(
+            "{} trailing bytes after the transaction",
+            raw.len() - reader.pos
+        ));
+    }
+    Ok(tx)
+}
+
+// reads one transaction and leaves the reader right after it
+pub(crate) fn read_transaction(reader: &mut Reader) -> Result<Transaction, String> {
+    let version = reader.u32()?;
+
+    let mut input_count = reader.varint()?;
+    let segwit = input_count == 0;
+    if segwit {
+        if reader.u8()? != 0x01 {
+            return Err("unknown segwit flag".to_string());
+        }
+        input_count = reader.varint()?;
+    }
+
+    let mut vin = Vec::new();
+    for _ in 0..input_count {
+        let mut txid = reader.take(32)?.to_vec();
+        txid.reverse();
+        let vout = reader.u32()?;
+        let scriptsig = reader.var_bytes()?;
+        let sequence = reader.u32()?;
+
+        vin.push(Input {
+            is_coinbase: txid.iter().all(|b| *b == 0) && vout == 0xffffffff,
+            txid: hex::encode(txid),
+            vout,
+            prevout: PrevOut {
+                scriptpubkey: String::new(),
+                scriptpubkey_asm: String::new(),
+                scriptpubkey_type: "unknown".to_string(),
+                scriptpubkey_address: None,
+                value: 0,
+            },
+            scriptsig: hex::encode(scriptsig),
+            scriptsig_asm: disassemble(scriptsig),
+            witness: None,
+            sequence,
+            inner_witnessscript_asm: None,
+            inner_redeemscript_asm: None,
+        });
+    }
+
+    let output_count = reader.varint()?;
+    let mut vout = Vec::new();
+    for _ in 0..output_count {
+        let value = reader.u64()?;
+        let scriptpubkey = reader.var_bytes()?;
+        vout.push(Output {
+            scriptpubkey: hex::encode(scriptpubkey),
+            scriptpubkey_asm: disassemble(scriptpubkey),
+            scriptpubkey_type: classify(scriptpubkey).label().to_string(),
+            scriptpubkey_address: None,
+            value,
+        });
+    }
+
+    if segwit {
+        for ins in vin.iter_mut() {
+            let items = reader.varint()?;
+            let mut witness = Vec::new();
+            for _ in 0..items {
+                witness.push(hex::encode(reader.var_bytes()?));
+            }
+            if !witness.is_empty() {
+                ins.witness = Some(witness);
+            }
+        }
+    }
+
+    let locktime = reader.u32()?;
+
+    Ok(Transaction {
+        version,
+        locktime,
+        vin,
+        vout,
+    })
+}
+
+// the marker, flag and witnesses are only written when asked for and some input has a witness
+pub(crate) fn serialize_transaction(tx: &Transaction, with_witness: bool) -> Vec<u8> {
+    let with_witness = with_witness && tx.vin.iter().any(|ins| ins.witness.is_some());
+    let mut raw: Vec<u8> = Vec::new();
+
+    raw.extend_from_slice(&tx.version.to_le_bytes());
+    if with_witness {
+        raw.push(0x00);
+        raw.push(0x01);
+    }
+
+    raw.extend_from_slice(&turn_to_varint(tx.vin.len() as u64));
+    for ins in &tx.vin {
+        let mut txid = hex::decode(&ins.txid).unwrap();
+        txid.reverse();
+        raw.extend_from_slice(&txid);
+        raw.extend_from_slice(&ins.vout.to_le_bytes());
+        let scriptsig = hex::decode(&ins.scriptsig).unwrap();
+        raw.extend_from_slice(&turn_to_varint(scriptsig.len() as u64));
+        raw.extend_from_slice(&scriptsig);
+        raw.extend_from_slice(&ins.sequence.to_le_bytes());
+    }
+
+    raw.extend_from_slice(&turn_to_varint(tx.vout.len() as u64));
+    for outs in &tx.vout {
+        raw.extend_from_slice(&outs.value.to_le_bytes());
+        let scriptpubkey = hex::decode(&outs.scriptpubkey).unwrap();
+        raw.extend_from_slice(&turn_to_varint(scriptpubkey.len() as u64));
+        raw.extend_from_slice(&scriptpubkey);
+    }
+
+    if with_witness {
+        for ins in &tx.vin {
+            let witness = ins.witness.clone().unwrap_or_default();
+            raw.extend_from_slice(&turn_to_varint(witness.len() as u64));
+            for item in witness {
+                let item = hex::decode(item).unwrap();
+                raw.extend_from_slice(&turn_to_varint(item.len() as u64));
+                raw.extend_from_slice(&item);
+            }
+        }
+    }
+
+    raw.extend_from_slice(&tx.locktime.to_le_bytes());
+    raw
+}
+
+// displayed txid of a serialized transaction, witness or not
+pub(crate) fn raw_txid(raw: &[u8]) -> Result<String, String> {
+    let tx = decode_raw_transaction(raw)?;
+    let mut txid = sha256_hash(&sha256_hash(&serialize_transaction(&tx, false)));
+    txid.reverse();
+    Ok(hex::encode(txid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{collect_txids, get_wtxid};
+    use std::fs;
+
+    #[test]
+    fn test_raw_roundtrip() {
+        for entry in fs::read_dir("../mempool_test").unwrap() {
+            let tx: Transaction =
+                serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap();
+
+            let raw = serialize_transaction(&tx, true);
+            let decoded = decode_raw_transaction(&raw).unwrap();
+            assert_eq!(serialize_transaction(&decoded, true), raw);
+            assert_eq!(collect_txids(&decoded), collect_txids(&tx));
+            assert_eq!(raw_txid(&raw).unwrap(), collect_txids(&tx));
+            for (decoded, outs) in decoded.vout.iter().zip(&tx.vout) {
+                assert_eq!(decoded.scriptpubkey_type, outs.scriptpubkey_type);
+            }
+
+            let mut wtxid = sha256_hash(&sha256_hash(&raw));
+            wtxid.reverse();
+            assert_eq!(wtxid, get_wtxid(&tx));
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_trailing_bytes() {
+        for entry in fs::read_dir("../mempool_test").unwrap() {
+            let tx: Transaction =
+                serde_json::from_str(&fs::read_to_string(entry.unwrap().path()).unwrap()).unwrap();
+
+            let mut raw = serialize_transaction(&tx, true);
+            raw.push(0x00);
+            assert!(decode_raw_transaction(&raw).is_err());
+            raw.truncate(raw.len() - 10);
+            assert!(decode_raw_transaction(&raw).is_err());
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::arena::{TxArena, TxIndex};
+use crate::feerate::FeeRate;
+use crate::TxNode;
+
+// lower bounds of the feerate histogram buckets in sat/vB, the last bucket is open ended
+const FEERATE_BUCKETS: [u64; 16] = [0, 1, 2, 3, 4, 5, 6, 8, 10, 12, 15, 20, 30, 50, 100, 200];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    MissingInputs,
+    InsufficientInputs,
+    InvalidSignature,
+    NonFinalLocktime,
+    Denylisted,
+    // spends an output of a mempool transaction that was rejected
+    RejectedParent,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::MissingInputs => "missing_inputs",
+            RejectReason::InsufficientInputs => "insufficient_inputs",
+            RejectReason::InvalidSignature => "invalid_signature",
+            RejectReason::NonFinalLocktime => "non_final_locktime",
+            RejectReason::Denylisted => "denylisted",
+            RejectReason::RejectedParent => "rejected_parent",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct FeerateBucket {
+    pub min_sat_vb: u64,
+    pub max_sat_vb: Option<u64>,
+    pub count: usize,
+    pub weight: u64,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ScriptTypeStats {
+    pub inputs: usize,
+    pub outputs: usize,
+    // weight of the transactions spending at least one input of this type
+    pub spending_tx_weight: u64,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct MempoolReport {
+    pub transactions_seen: usize,
+    pub transactions_valid: usize,
+    pub transactions_included: usize,
+    pub block_weight: u64,
+    pub fees_included: u64,
+    pub fees_left_out: u64,
+    // lowest feerate among the transactions in the block, in sat/vB. the block is in parent
+    // first order, so the last one in it can pay well above what got a transaction in
+    pub marginal_feerate: Option<f64>,
+    pub feerate_histogram: Vec<FeerateBucket>,
+    pub script_types: BTreeMap<String, ScriptTypeStats>,
+    pub ancestor_depths: BTreeMap<usize, usize>,
+    pub descendant_depths: BTreeMap<usize, usize>,
+    pub rejections: BTreeMap<String, usize>,
+    // transactions whose asm fields disagree with their script hex
+    pub asm_mismatches: usize,
+    // and whose scriptpubkey_address fields disagree with their scriptPubKey
+    pub address_mismatches: usize,
+    // and whose scriptpubkey_type labels don't fit their scriptPubKey
+    pub type_mismatches: usize,
+    // transactions whose signatures weren't verified again thanks to the signature cache
+    pub signature_cache_hits: usize,
+}
+
+impl MempoolReport {
+    pub fn new() -> Self {
+        let feerate_histogram = FEERATE_BUCKETS
+            .iter()
+            .enumerate()
+            .map(|(i, min)| FeerateBucket {
+                min_sat_vb: *min,
+                max_sat_vb: FEERATE_BUCKETS.get(i + 1).copied(),
+                count: 0,
+                weight: 0,
+            })
+            .collect();
+
+        MempoolReport {
+            feerate_histogram,
+            ..Default::default()
+        }
+    }
+
+    pub fn record_rejection(&mut self, reason: RejectReason) {
+        *self
+            .rejections
+            .entry(reason.as_str().to_string())
+            .or_default() += 1;
+    }
+
+    // fills in everything that depends on the set of valid transactions and the selected block
+    pub(crate) fn record_block(&mut self, valid: &[&TxNode], arena: &TxArena, accepted: &[String]) {
+        let accepted_set: HashSet<&str> = accepted.iter().map(|t| t.as_str()).collect();
+
+        self.transactions_valid = valid.len();
+        self.transactions_included = accepted.len();
+
+        let mut lowest: Option<FeeRate> = None;
+        for node in valid {
+            let bucket = self
+                .feerate_histogram
+                .iter_mut()
+                .rev()
+                .find(|b| node.feerate() >= FeeRate::from_sat_per_vbyte(b.min_sat_vb))
+                .unwrap();
+            bucket.count += 1;
+            bucket.weight += node.weight;
+
+            // grouped by the classified scriptPubKey rather than the JSON label
+            let mut spent_types: Vec<&str> = Vec::new();
+            let record = &arena[node.index];
+            for script_type in &record.input_types {
+                let script_type = script_type.label();
+                let stats = self
+                    .script_types
+                    .entry(script_type.to_string())
+                    .or_default();
+                stats.inputs += 1;
+                if !spent_types.contains(&script_type) {
+                    stats.spending_tx_weight += node.weight;
+                    spent_types.push(script_type);
+                }
+            }
+            for script_type in &record.output_types {
+                self.script_types
+                    .entry(script_type.label().to_string())
+                    .or_default()
+                    .outputs += 1;
+            }
+
+            if accepted_set.contains(node.txid.as_str()) {
+                self.fees_included += node.fee;
+                self.block_weight += node.weight;
+                let feerate = node.feerate();
+                lowest = Some(lowest.map_or(feerate, |lowest| lowest.min(feerate)));
+            } else {
+                self.fees_left_out += node.fee;
+            }
+        }
+
+        self.marginal_feerate = lowest.map(|rate| rate.sat_per_vbyte());
+
+        self.record_depths(valid, arena);
+    }
+
+    fn record_depths(&mut self, valid: &[&TxNode], arena: &TxArena) {
+        let position: HashMap<TxIndex, usize> = valid
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.index, i))
+            .collect();
+
+        let mut parents: Vec<Vec<usize>> = vec![Vec::new(); valid.len()];
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); valid.len()];
+        for (i, node) in valid.iter().enumerate() {
+            for p in &arena[node.index].parents {
+                if let Some(&p) = position.get(p) {
+                    parents[i].push(p);
+                    children[p].push(i);
+                }
+            }
+        }
+
+        let mut memo = vec![None; valid.len()];
+        for i in 0..valid.len() {
+            *self
+                .ancestor_depths
+                .entry(longest_chain(i, &parents, &mut memo))
+                .or_default() += 1;
+        }
+
+        let mut memo = vec![None; valid.len()];
+        for i in 0..valid.len() {
+            *self
+                .descendant_depths
+                .entry(longest_chain(i, &children, &mut memo))
+                .or_default() += 1;
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+impl fmt::Display for MempoolReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Transactions seen: {}", self.transactions_seen)?;
+        writeln!(f, "Transactions valid: {}", self.transactions_valid)?;
+        writeln!(f, "Transactions included: {}", self.transactions_included)?;
+        writeln!(f, "Block weight: {}", self.block_weight)?;
+        writeln!(f, "Fees included: {}", self.fees_included)?;
+        writeln!(f, "Fees left out: {}", self.fees_left_out)?;
+        match self.marginal_feerate {
+            Some(rate) => writeln!(f, "Marginal feerate: {:.2} sat/vB", rate)?,
+            None => writeln!(f, "Marginal feerate: n/a")?,
+        }
+
+        writeln!(f, "\nFeerate histogram (sat/vB):")?;
+        for bucket in &self.feerate_histogram {
+            let range = match bucket.max_sat_vb {
+                Some(max) => format!("{}-{}", bucket.min_sat_vb, max),
+                None => format!("{}+", bucket.min_sat_vb),
+            };
+            writeln!(
+                f,
+                "  {:>8}: {:>6} txs {:>10} WU",
+                range, bucket.count, bucket.weight
+            )?;
+        }
+
+        writeln!(f, "\nScript types:")?;
+        for (script_type, stats) in &self.script_types {
+            writeln!(
+                f,
+                "  {:>10}: {:>6} inputs {:>6} outputs {:>10} WU",
+                script_type, stats.inputs, stats.outputs, stats.spending_tx_weight
+            )?;
+        }
+
+        writeln!(f, "\nAncestor depth:")?;
+        for (depth, count) in &self.ancestor_depths {
+            writeln!(f, "  {:>3}: {}", depth, count)?;
+        }
+
+        writeln!(f, "\nDescendant depth:")?;
+        for (depth, count) in &self.descendant_depths {
+            writeln!(f, "  {:>3}: {}", depth, count)?;
+        }
+
+        writeln!(f, "\nRejections:")?;
+        for (reason, count) in &self.rejections {
+            writeln!(f, "  {}: {}", reason, count)?;
+        }
+        writeln!(f, "\nAsm mismatches: {}", self.asm_mismatches)?;
+        writeln!(f, "Address mismatches: {}", self.address_mismatches)?;
+        writeln!(f, "Type mismatches: {}", self.type_mismatches)?;
+        writeln!(f, "Signature cache hits: {}", self.signature_cache_hits)?;
+        Ok(())
+    }
+}
+
+fn longest_chain(i: usize, edges: &[Vec<usize>], memo: &mut Vec<Option<usize>>) -> usize {
+    if let Some(depth) = memo[i] {
+        return depth;
+    }
+    let mut depth = 0;
+    for &next in &edges[i] {
+        depth = depth.max(1 + longest_chain(next, edges, memo));
+    }
+    memo[i] = Some(depth);
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feerate_buckets() {
+        let report = MempoolReport::new();
+        assert_eq!(report.feerate_histogram.len(), FEERATE_BUCKETS.len());
+        assert_eq!(report.feerate_histogram[1].min_sat_vb, 1);
+        assert_eq!(report.feerate_histogram[1].max_sat_vb, Some(2));
+        assert_eq!(report.feerate_histogram.last().unwrap().max_sat_vb, None);
+    }
+
+    #[test]
+    fn test_longest_chain() {
+        // 0 <- 1 <- 2, 3 has no parents
+        let parents = vec![vec![], vec![0], vec![1], vec![]];
+        let mut memo = vec![None; 4];
+        assert_eq!(longest_chain(2, &parents, &mut memo), 2);
+        assert_eq!(longest_chain(3, &parents, &mut memo), 0);
+    }
+
+    #[test]
+    fn test_marginal_feerate_is_the_lowest_included() {
+        // b pays for a, its parent, and comes last in the block. c is left out
+        let (arena, valid) = crate::selection::tests::mempool(&[
+            (100, 400, &[]),
+            (10000, 400, &[0]),
+            (50, 400, &[]),
+        ]);
+        let valid: Vec<&TxNode> = valid.iter().collect();
+        let accepted = vec![valid[0].txid.clone(), valid[1].txid.clone()];
+
+        let mut report = MempoolReport::new();
+        report.record_block(&valid, &arena, &accepted);
+        assert_eq!(report.marginal_feerate, Some(1.0));
+
+        report.record_block(&valid, &arena, &[]);
+        assert_eq!(report.marginal_feerate, None);
+    }
+}
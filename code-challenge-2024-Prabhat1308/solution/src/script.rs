@@ -0,0 +1,529 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+// opcodes the rest of the crate refers to by name
+pub const OP_0: u8 = 0x00;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHDATA2: u8 = 0x4d;
+pub const OP_PUSHDATA4: u8 = 0x4e;
+pub const OP_1NEGATE: u8 = 0x4f;
+pub const OP_1: u8 = 0x51;
+pub const OP_16: u8 = 0x60;
+pub const OP_RETURN: u8 = 0x6a;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_EQUAL: u8 = 0x87;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_HASH160: u8 = 0xa9;
+pub const OP_CHECKSIG: u8 = 0xac;
+pub const OP_CHECKMULTISIG: u8 = 0xae;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Op(u8),
+    // any of OP_0, OP_PUSHBYTES_n and OP_PUSHDATA1/2/4 together with the bytes it pushes
+    Push { opcode: u8, data: Vec<u8> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    // a push runs past the end of the script
+    UnexpectedEnd { offset: usize },
+    UnknownOpcode(String),
+    // a push opcode in asm without the data that goes with it
+    MissingPushData(String),
+    InvalidHex(String),
+    // the data doesn't fit the push opcode it follows, e.g. OP_PUSHBYTES_20 with 19 bytes
+    PushLengthMismatch(String),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::UnexpectedEnd { offset } => {
+                write!(f, "push at byte {} runs past the end of the script", offset)
+            }
+            ScriptError::UnknownOpcode(op) => write!(f, "unknown opcode {}", op),
+            ScriptError::MissingPushData(op) => write!(f, "{} is missing its data", op),
+            ScriptError::InvalidHex(data) => write!(f, "invalid hex {}", data),
+            ScriptError::PushLengthMismatch(op) => {
+                write!(f, "data doesn't match the length of {}", op)
+            }
+        }
+    }
+}
+
+impl Error for ScriptError {}
+
+pub fn parse_script(script: &[u8]) -> Result<Vec<Instruction>, ScriptError> {
+    let mut instructions = Vec::new();
+    let mut i = 0;
+
+    while i < script.len() {
+        let opcode = script[i];
+        let start = i;
+        i += 1;
+
+        let len = match opcode {
+            0x00..=0x4b => opcode as usize,
+            OP_PUSHDATA1 => read_len(script, &mut i, 1, start)?,
+            OP_PUSHDATA2 => read_len(script, &mut i, 2, start)?,
+            OP_PUSHDATA4 => read_len(script, &mut i, 4, start)?,
+            _ => {
+                instructions.push(Instruction::Op(opcode));
+                continue;
+            }
+        };
+
+        if i + len > script.len() {
+            return Err(ScriptError::UnexpectedEnd { offset: start });
+        }
+        instructions.push(Instruction::Push {
+            opcode,
+            data: script[i..i + len].to_vec(),
+        });
+        i += len;
+    }
+
+    Ok(instructions)
+}
+
+fn read_len(script: &[u8], i: &mut usize, size: usize, start: usize) -> Result<usize, ScriptError> {
+    if *i + size > script.len() {
+        return Err(ScriptError::UnexpectedEnd { offset: start });
+    }
+    let mut len: usize = 0;
+    for (shift, byte) in script[*i..*i + size].iter().enumerate() {
+        len |= (*byte as usize) << (8 * shift);
+    }
+    *i += size;
+    Ok(len)
+}
+
+// names as printed by mempool.space / esplora, which is where the *_asm fields come from
+pub fn opcode_name(opcode: u8) -> String {
+    let name = match opcode {
+        0x00 => "OP_0",
+        0x01..=0x4b => return format!("OP_PUSHBYTES_{}", opcode),
+        0x4c => "OP_PUSHDATA1",
+        0x4d => "OP_PUSHDATA2",
+        0x4e => "OP_PUSHDATA4",
+        0x4f => "OP_PUSHNUM_NEG1",
+        0x50 => "OP_RESERVED",
+        0x51..=0x60 => return format!("OP_PUSHNUM_{}", opcode - 0x50),
+        0x61 => "OP_NOP",
+        0x62 => "OP_VER",
+        0x63 => "OP_IF",
+        0x64 => "OP_NOTIF",
+        0x65 => "OP_VERIF",
+        0x66 => "OP_VERNOTIF",
+        0x67 => "OP_ELSE",
+        0x68 => "OP_ENDIF",
+        0x69 => "OP_VERIFY",
+        0x6a => "OP_RETURN",
+        0x6b => "OP_TOALTSTACK",
+        0x6c => "OP_FROMALTSTACK",
+        0x6d => "OP_2DROP",
+        0x6e => "OP_2DUP",
+        0x6f => "OP_3DUP",
+        0x70 => "OP_2OVER",
+        0x71 => "OP_2ROT",
+        0x72 => "OP_2SWAP",
+        0x73 => "OP_IFDUP",
+        0x74 => "OP_DEPTH",
+        0x75 => "OP_DROP",
+        0x76 => "OP_DUP",
+        0x77 => "OP_NIP",
+        0x78 => "OP_OVER",
+        0x79 => "OP_PICK",
+        0x7a => "OP_ROLL",
+        0x7b => "OP_ROT",
+        0x7c => "OP_SWAP",
+        0x7d => "OP_TUCK",
+        0x7e => "OP_CAT",
+        0x7f => "OP_SUBSTR",
+        0x80 => "OP_LEFT",
+        0x81 => "OP_RIGHT",
+        0x82 => "OP_SIZE",
+        0x83 => "OP_INVERT",
+        0x84 => "OP_AND",
+        0x85 => "OP_OR",
+        0x86 => "OP_XOR",
+        0x87 => "OP_EQUAL",
+        0x88 => "OP_EQUALVERIFY",
+        0x89 => "OP_RESERVED1",
+        0x8a => "OP_RESERVED2",
+        0x8b => "OP_1ADD",
+        0x8c => "OP_1SUB",
+        0x8d => "OP_2MUL",
+        0x8e => "OP_2DIV",
+        0x8f => "OP_NEGATE",
+        0x90 => "OP_ABS",
+        0x91 => "OP_NOT",
+        0x92 => "OP_0NOTEQUAL",
+        0x93 => "OP_ADD",
+        0x94 => "OP_SUB",
+        0x95 => "OP_MUL",
+        0x96 => "OP_DIV",
+        0x97 => "OP_MOD",
+        0x98 => "OP_LSHIFT",
+        0x99 => "OP_RSHIFT",
+        0x9a => "OP_BOOLAND",
+        0x9b => "OP_BOOLOR",
+        0x9c => "OP_NUMEQUAL",
+        0x9d => "OP_NUMEQUALVERIFY",
+        0x9e => "OP_NUMNOTEQUAL",
+        0x9f => "OP_LESSTHAN",
+        0xa0 => "OP_GREATERTHAN",
+        0xa1 => "OP_LESSTHANOREQUAL",
+        0xa2 => "OP_GREATERTHANOREQUAL",
+        0xa3 => "OP_MIN",
+        0xa4 => "OP_MAX",
+        0xa5 => "OP_WITHIN",
+        0xa6 => "OP_RIPEMD160",
+        0xa7 => "OP_SHA1",
+        0xa8 => "OP_SHA256",
+        0xa9 => "OP_HASH160",
+        0xaa => "OP_HASH256",
+        0xab => "OP_CODESEPARATOR",
+        0xac => "OP_CHECKSIG",
+        0xad => "OP_CHECKSIGVERIFY",
+        0xae => "OP_CHECKMULTISIG",
+        0xaf => "OP_CHECKMULTISIGVERIFY",
+        0xb0 => "OP_NOP1",
+        0xb1 => "OP_CLTV",
+        0xb2 => "OP_CSV",
+        0xb3..=0xb9 => return format!("OP_NOP{}", opcode - 0xaf),
+        0xba => "OP_CHECKSIGADD",
+        0xbb..=0xfe => return format!("OP_RETURN_{}", opcode),
+        0xff => "OP_INVALIDOPCODE",
+    };
+    name.to_string()
+}
+
+pub fn disassemble(script: &[u8]) -> String {
+    let mut parts: Vec<String> = Vec::new();
+
+    let (instructions, truncated) = match parse_script(script) {
+        Ok(instructions) => (instructions, false),
+        Err(ScriptError::UnexpectedEnd { offset }) => {
+            // keep whatever decodes before the broken push
+            (parse_script(&script[..offset]).unwrap_or_default(), true)
+        }
+        // parse_script fails on nothing but truncated pushes
+        Err(_) => unreachable!(),
+    };
+
+    for instruction in instructions {
+        match instruction {
+            Instruction::Op(opcode) => parts.push(opcode_name(opcode)),
+            Instruction::Push { opcode, data } => {
+                parts.push(opcode_name(opcode));
+                if !data.is_empty() {
+                    parts.push(hex::encode(data));
+                }
+            }
+        }
+    }
+    if truncated {
+        parts.push("<unexpected end>".to_string());
+    }
+
+    parts.join(" ")
+}
+
+// inverse of disassemble, accepts exactly the asm it produces
+pub fn assemble(asm: &str) -> Result<Vec<u8>, ScriptError> {
+    let opcodes: HashMap<String, u8> = (0..=255u8).map(|op| (opcode_name(op), op)).collect();
+
+    let mut script = Vec::new();
+    let mut tokens = asm.split_whitespace();
+
+    while let Some(token) = tokens.next() {
+        let opcode = *opcodes
+            .get(token)
+            .ok_or_else(|| ScriptError::UnknownOpcode(token.to_string()))?;
+
+        if opcode == OP_0 || opcode > OP_PUSHDATA4 {
+            script.push(opcode);
+            continue;
+        }
+
+        let data_hex = tokens
+            .next()
+            .ok_or_else(|| ScriptError::MissingPushData(token.to_string()))?;
+        let data =
+            hex::decode(data_hex).map_err(|_| ScriptError::InvalidHex(data_hex.to_string()))?;
+
+        script.push(opcode);
+        let fits = match opcode {
+            OP_PUSHDATA1 => {
+                script.push(data.len() as u8);
+                data.len() <= 0xff
+            }
+            OP_PUSHDATA2 => {
+                script.extend_from_slice(&(data.len() as u16).to_le_bytes());
+                data.len() <= 0xffff
+            }
+            OP_PUSHDATA4 => {
+                script.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                true
+            }
+            _ => data.len() == opcode as usize,
+        };
+        if !fits {
+            return Err(ScriptError::PushLengthMismatch(token.to_string()));
+        }
+        script.extend_from_slice(&data);
+    }
+
+    Ok(script)
+}
+
+// data of every push in the script, empty when the script doesn't parse
+pub fn pushed_data(script: &[u8]) -> Vec<Vec<u8>> {
+    parse_script(script)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::Push { data, .. } => Some(data),
+            Instruction::Op(_) => None,
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptType {
+    P2pk,
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    // pay to anchor, OP_1 <4e73>
+    P2a,
+    // witness program of a version or length without defined meaning yet
+    WitnessUnknown { version: u8 },
+    // bare m-of-n OP_CHECKMULTISIG
+    Multisig { required: u8, keys: u8 },
+    OpReturn,
+    NonStandard,
+}
+
+impl ScriptType {
+    // names as esplora uses them in the scriptpubkey_type field
+    pub fn label(&self) -> &'static str {
+        match self {
+            ScriptType::P2pk => "p2pk",
+            ScriptType::P2pkh => "p2pkh",
+            ScriptType::P2sh => "p2sh",
+            ScriptType::P2wpkh => "v0_p2wpkh",
+            ScriptType::P2wsh => "v0_p2wsh",
+            ScriptType::P2tr => "v1_p2tr",
+            ScriptType::P2a => "anchor",
+            ScriptType::WitnessUnknown { .. } => "witness_unknown",
+            ScriptType::Multisig { .. } => "multisig",
+            ScriptType::OpReturn => "op_return",
+            ScriptType::NonStandard => "nonstandard",
+        }
+    }
+
+    // whether a scriptpubkey_type label fits this script, older indexers call everything
+    // past the common types "unknown"
+    pub fn matches_label(&self, label: &str) -> bool {
+        match self {
+            ScriptType::P2a
+            | ScriptType::WitnessUnknown { .. }
+            | ScriptType::Multisig { .. }
+            | ScriptType::NonStandard => label == self.label() || label == "unknown",
+            _ => label == self.label(),
+        }
+    }
+}
+
+// small integer pushed by OP_1 to OP_16
+fn small_int(opcode: u8) -> Option<u8> {
+    if (OP_1..=OP_16).contains(&opcode) {
+        Some(opcode - OP_1 + 1)
+    } else {
+        None
+    }
+}
+
+pub fn classify(script: &[u8]) -> ScriptType {
+    match script {
+        [OP_DUP, OP_HASH160, 0x14, hash @ .., OP_EQUALVERIFY, OP_CHECKSIG] if hash.len() == 20 => {
+            ScriptType::P2pkh
+        }
+        [OP_HASH160, 0x14, hash @ .., OP_EQUAL] if hash.len() == 20 => ScriptType::P2sh,
+        [OP_0, 0x14, program @ ..] if program.len() == 20 => ScriptType::P2wpkh,
+        [OP_0, 0x20, program @ ..] if program.len() == 32 => ScriptType::P2wsh,
+        [OP_1, 0x20, program @ ..] if program.len() == 32 => ScriptType::P2tr,
+        [OP_1, 0x02, 0x4e, 0x73] => ScriptType::P2a,
+        // any other v1+ program, v0 ones of other lengths can never be spent
+        [version, len, program @ ..]
+            if small_int(*version).is_some()
+                && *len as usize == program.len()
+                && (2..=40).contains(&program.len()) =>
+        {
+            ScriptType::WitnessUnknown {
+                version: small_int(*version).unwrap(),
+            }
+        }
+        [OP_RETURN, ..] => ScriptType::OpReturn,
+        [len, key @ .., OP_CHECKSIG]
+            if *len as usize == key.len() && (key.len() == 33 || key.len() == 65) =>
+        {
+            ScriptType::P2pk
+        }
+        _ => classify_multisig(script).unwrap_or(ScriptType::NonStandard),
+    }
+}
+
+// OP_m <key>... OP_n OP_CHECKMULTISIG with n keys of 33 or 65 bytes
+fn classify_multisig(script: &[u8]) -> Option<ScriptType> {
+    let instructions = parse_script(script).ok()?;
+    let (first, rest) = instructions.split_first()?;
+    let (last, rest) = rest.split_last()?;
+    let (count, keys) = rest.split_last()?;
+
+    let required = match first {
+        Instruction::Op(opcode) => small_int(*opcode)?,
+        _ => return None,
+    };
+    let total = match count {
+        Instruction::Op(opcode) => small_int(*opcode)?,
+        _ => return None,
+    };
+    let all_keys = keys.iter().all(
+        |key| matches!(key, Instruction::Push { data, .. } if data.len() == 33 || data.len() == 65),
+    );
+
+    if *last != Instruction::Op(OP_CHECKMULTISIG)
+        || !all_keys
+        || keys.len() != total as usize
+        || required > total
+    {
+        return None;
+    }
+    Some(ScriptType::Multisig {
+        required,
+        keys: total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_p2pkh() {
+        let script = hex::decode("76a914c0e8c1c4e04b1b1d8fb0b8c25ccc0d6b5e3bd0a988ac").unwrap();
+        assert_eq!(
+            disassemble(&script),
+            "OP_DUP OP_HASH160 OP_PUSHBYTES_20 c0e8c1c4e04b1b1d8fb0b8c25ccc0d6b5e3bd0a9 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_pushdata_and_numbers() {
+        let mut script = vec![OP_0, 0x4f, 0x52, 0xb2, OP_PUSHDATA1, 0x03, 0xaa, 0xbb, 0xcc];
+        assert_eq!(
+            disassemble(&script),
+            "OP_0 OP_PUSHNUM_NEG1 OP_PUSHNUM_2 OP_CSV OP_PUSHDATA1 aabbcc"
+        );
+
+        script.pop();
+        assert_eq!(
+            disassemble(&script),
+            "OP_0 OP_PUSHNUM_NEG1 OP_PUSHNUM_2 OP_CSV <unexpected end>"
+        );
+        assert_eq!(
+            parse_script(&script),
+            Err(ScriptError::UnexpectedEnd { offset: 4 })
+        );
+    }
+
+    #[test]
+    fn test_assemble_roundtrip() {
+        let asm = "OP_PUSHNUM_2 OP_PUSHBYTES_33 029dd8aa207edff998ec6611e760f0ecf751c89df612ad28771471ac941990015e OP_PUSHBYTES_33 02201a3508a47a9d1ad5f2f0506d3092a43fef79706c05bf6a29525b5339e0e513 OP_PUSHNUM_2 OP_CHECKMULTISIG";
+        let script = assemble(asm).unwrap();
+        assert_eq!(script[0], 0x52);
+        assert_eq!(*script.last().unwrap(), OP_CHECKMULTISIG);
+        assert_eq!(disassemble(&script), asm);
+
+        let all_ops: Vec<u8> = (0x4f..=0xff).collect();
+        assert_eq!(assemble(&disassemble(&all_ops)).unwrap(), all_ops);
+
+        let pushdata = assemble("OP_PUSHDATA2 aabb OP_0").unwrap();
+        assert_eq!(pushdata, vec![OP_PUSHDATA2, 0x02, 0x00, 0xaa, 0xbb, OP_0]);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(
+            assemble("OP_FOO"),
+            Err(ScriptError::UnknownOpcode("OP_FOO".to_string()))
+        );
+        assert_eq!(
+            assemble("OP_PUSHBYTES_2"),
+            Err(ScriptError::MissingPushData("OP_PUSHBYTES_2".to_string()))
+        );
+        assert_eq!(
+            assemble("OP_PUSHBYTES_2 aa"),
+            Err(ScriptError::PushLengthMismatch(
+                "OP_PUSHBYTES_2".to_string()
+            ))
+        );
+        assert_eq!(
+            assemble("OP_PUSHBYTES_1 zz"),
+            Err(ScriptError::InvalidHex("zz".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_classify() {
+        let cases = [
+            ("76a914c0e8c1c4e04b1b1d8fb0b8c25ccc0d6b5e3bd0a988ac", ScriptType::P2pkh),
+            ("a914748284390f9e263a4b766a75d0633c50426eb87587", ScriptType::P2sh),
+            ("0014751e76e8199196d454941c45d1b3a323f1433bd6", ScriptType::P2wpkh),
+            (
+                "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+                ScriptType::P2wsh,
+            ),
+            (
+                "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+                ScriptType::P2tr,
+            ),
+            ("51024e73", ScriptType::P2a),
+            ("5210751e76e8199196d454941c45d1b3a323", ScriptType::WitnessUnknown { version: 2 }),
+            ("6a0401020304", ScriptType::OpReturn),
+            (
+                "21029dd8aa207edff998ec6611e760f0ecf751c89df612ad28771471ac941990015eac",
+                ScriptType::P2pk,
+            ),
+            (
+                "5121029dd8aa207edff998ec6611e760f0ecf751c89df612ad28771471ac941990015e2102201a3508a47a9d1ad5f2f0506d3092a43fef79706c05bf6a29525b5339e0e51352ae",
+                ScriptType::Multisig { required: 1, keys: 2 },
+            ),
+            // v0 program of the wrong length
+            ("0015751e76e8199196d454941c45d1b3a323f1433bd6aa", ScriptType::NonStandard),
+            ("", ScriptType::NonStandard),
+        ];
+
+        for (script_hex, expected) in cases {
+            assert_eq!(
+                classify(&hex::decode(script_hex).unwrap()),
+                expected,
+                "{}",
+                script_hex
+            );
+        }
+        assert!(ScriptType::Multisig {
+            required: 1,
+            keys: 2
+        }
+        .matches_label("unknown"));
+        assert!(!ScriptType::P2wpkh.matches_label("unknown"));
+    }
+}
@@ -0,0 +1,314 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::arena::{TxArena, TxIndex};
+use crate::feerate::FeeRate;
+use crate::TxNode;
+
+// parents of each transaction among the valid ones, nodes are positions in `valid`
+fn parents(valid: &[TxNode], arena: &TxArena) -> Vec<Vec<usize>> {
+    let position: HashMap<TxIndex, usize> = valid
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.index, i))
+        .collect();
+
+    valid
+        .iter()
+        .map(|node| {
+            arena[node.index]
+                .parents
+                .iter()
+                .filter_map(|p| position.get(p).copied())
+                .collect()
+        })
+        .collect()
+}
+
+pub(crate) fn select_greedy(valid: &[TxNode], arena: &TxArena, max_weight: u64) -> Vec<TxNode> {
+    let parents = parents(valid, arena);
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); valid.len()];
+    for (i, ps) in parents.iter().enumerate() {
+        for &p in ps {
+            children[p].push(i);
+        }
+    }
+
+    // a transaction is a candidate once all of its parents are in the block, to begin with
+    // the ones that have none
+    let mut incomings: Vec<usize> = parents.iter().map(|ps| ps.len()).collect();
+    let mut heap: BinaryHeap<(&TxNode, usize)> = valid
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| incomings[*i] == 0)
+        .map(|(i, node)| (node, i))
+        .collect();
+
+    let mut block_weight: u64 = 0;
+    let mut selected: Vec<TxNode> = Vec::new();
+
+    while let Some((node, i)) = heap.pop() {
+        // if the weight of the block after adding the node is less than the max weight, add the node to the block
+        if block_weight + node.weight <= max_weight {
+            block_weight += node.weight;
+
+            for &child in &children[i] {
+                incomings[child] -= 1;
+                if incomings[child] == 0 {
+                    heap.push((&valid[child], child));
+                }
+            }
+            selected.push(node.clone());
+        }
+    }
+
+    selected
+}
+
+// Bitcoin Core's selection, as the block builder runs it: the transaction whose ancestors not
+// yet in the block pay the best feerate goes in along with them. package feerates sit in a
+// heap, an entry goes stale when an ancestor gets in and the descendant is pushed again with
+// its new score. a package that doesn't fit once stays out
+pub(crate) fn select_by_ancestor_feerate(
+    valid: &[TxNode],
+    arena: &TxArena,
+    max_weight: u64,
+) -> Vec<TxNode> {
+    let parents = parents(valid, arena);
+    let ancestors = ancestors(&parents);
+
+    let mut descendants: Vec<Vec<usize>> = vec![Vec::new(); valid.len()];
+    for (i, ancestor_set) in ancestors.iter().enumerate() {
+        for &a in ancestor_set {
+            descendants[a].push(i);
+        }
+    }
+
+    // fee and weight of each transaction together with its ancestors not yet in the block
+    let mut package_fee: Vec<u64> = Vec::with_capacity(valid.len());
+    let mut package_weight: Vec<u64> = Vec::with_capacity(valid.len());
+    for (i, node) in valid.iter().enumerate() {
+        package_fee.push(node.fee + ancestors[i].iter().map(|&a| valid[a].fee).sum::<u64>());
+        package_weight
+            .push(node.weight + ancestors[i].iter().map(|&a| valid[a].weight).sum::<u64>());
+    }
+
+    // equal feerates go to the lower txid
+    let mut version = vec![0u32; valid.len()];
+    let mut heap: BinaryHeap<(FeeRate, Reverse<&str>, usize, u32)> = valid
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let rate = FeeRate::new(package_fee[i], package_weight[i]);
+            (rate, Reverse(node.txid.as_str()), i, 0)
+        })
+        .collect();
+
+    let mut included = vec![false; valid.len()];
+    let mut failed = vec![false; valid.len()];
+    let mut block_weight: u64 = 0;
+    let mut selected: Vec<TxNode> = Vec::new();
+
+    while let Some((_, _, best, v)) = heap.pop() {
+        if included[best] || failed[best] || version[best] != v {
+            continue;
+        }
+
+        if block_weight + package_weight[best] > max_weight {
+            failed[best] = true;
+            continue;
+        }
+
+        // an ancestor always has fewer ancestors than its descendant, so this is a topological order
+        let mut package: Vec<usize> = ancestors[best]
+            .iter()
+            .copied()
+            .filter(|&a| !included[a])
+            .chain(std::iter::once(best))
+            .collect();
+        package.sort_by(|&a, &b| {
+            ancestors[a]
+                .len()
+                .cmp(&ancestors[b].len())
+                .then_with(|| valid[a].txid.cmp(&valid[b].txid))
+        });
+
+        let mut rescored: Vec<usize> = Vec::new();
+        for &i in &package {
+            included[i] = true;
+            block_weight += valid[i].weight;
+            selected.push(valid[i].clone());
+            for &d in &descendants[i] {
+                package_fee[d] -= valid[i].fee;
+                package_weight[d] -= valid[i].weight;
+                rescored.push(d);
+            }
+        }
+
+        rescored.sort_unstable();
+        rescored.dedup();
+        for d in rescored {
+            if included[d] || failed[d] {
+                continue;
+            }
+            version[d] += 1;
+            let rate = FeeRate::new(package_fee[d], package_weight[d]);
+            heap.push((rate, Reverse(valid[d].txid.as_str()), d, version[d]));
+        }
+    }
+
+    selected
+}
+
+// every ancestor of each transaction, sorted. parents come before their children in the
+// order transactions are visited, so each set is its parents' sets and the parents themselves
+fn ancestors(parents: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); parents.len()];
+    for (i, ps) in parents.iter().enumerate() {
+        for &p in ps {
+            children[p].push(i);
+        }
+    }
+    let mut incomings: Vec<usize> = parents.iter().map(|ps| ps.len()).collect();
+    let mut stack: Vec<usize> = (0..parents.len()).filter(|&i| incomings[i] == 0).collect();
+
+    let mut ancestors: Vec<Vec<usize>> = vec![Vec::new(); parents.len()];
+    while let Some(i) = stack.pop() {
+        let mut set: Vec<usize> = parents[i].clone();
+        for &p in &parents[i] {
+            set.extend_from_slice(&ancestors[p]);
+        }
+        set.sort_unstable();
+        set.dedup();
+        ancestors[i] = set;
+
+        for &child in &children[i] {
+            incomings[child] -= 1;
+            if incomings[child] == 0 {
+                stack.push(child);
+            }
+        }
+    }
+    ancestors
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::Transaction;
+
+    // one transaction per (fee, weight, parents), each spending an output of the earlier
+    // transactions it names. only the linkage comes from the arena, fee and weight are the
+    // node's
+    pub(crate) fn mempool(specs: &[(u64, u64, &[usize])]) -> (TxArena, Vec<TxNode>) {
+        let mut txids: Vec<String> = Vec::new();
+        let mut txs: Vec<Transaction> = Vec::new();
+        for (n, (_, _, parents)) in specs.iter().enumerate() {
+            let vin: Vec<serde_json::Value> = parents
+                .iter()
+                .map(|&p| {
+                    serde_json::json!({
+                        "txid": txids[p],
+                        "vout": 0,
+                        "prevout": {
+                            "scriptpubkey": "",
+                            "scriptpubkey_asm": "",
+                            "scriptpubkey_type": "v0_p2wpkh",
+                            "value": 0,
+                        },
+                        "scriptsig": "",
+                        "scriptsig_asm": "",
+                        "is_coinbase": false,
+                        "sequence": 0xffffffffu32,
+                    })
+                })
+                .collect();
+            // the locktime keeps transactions without inputs apart
+            let tx: Transaction = serde_json::from_value(serde_json::json!({
+                "version": 2,
+                "locktime": n,
+                "vin": vin,
+                "vout": [{
+                    "scriptpubkey": "",
+                    "scriptpubkey_asm": "",
+                    "scriptpubkey_type": "v0_p2wpkh",
+                    "value": 0,
+                }],
+            }))
+            .unwrap();
+            txids.push(crate::collect_txids(&tx));
+            txs.push(tx);
+        }
+
+        let arena = TxArena::build(txs.into_iter().map(Ok), |_, _| {}).unwrap();
+        let valid = arena
+            .iter()
+            .zip(specs)
+            .map(|((index, record), (fee, weight, _))| TxNode {
+                index,
+                txid: record.txid.clone(),
+                fee: *fee,
+                weight: *weight,
+            })
+            .collect();
+        (arena, valid)
+    }
+
+    fn positions(selected: &[TxNode]) -> Vec<TxIndex> {
+        selected.iter().map(|n| n.index).collect()
+    }
+
+    #[test]
+    fn test_ancestor_feerate_pulls_in_parent() {
+        let (arena, valid) = mempool(&[(100, 400, &[]), (10000, 400, &[0]), (1000, 400, &[])]);
+
+        let selected = select_by_ancestor_feerate(&valid, &arena, 800);
+        assert_eq!(positions(&selected), vec![0, 1]);
+    }
+
+    // the child pays the most but can only follow its parent
+    #[test]
+    fn test_greedy_puts_parents_first() {
+        let (arena, valid) = mempool(&[(100, 800, &[]), (10000, 400, &[0]), (1000, 400, &[])]);
+
+        let selected = select_greedy(&valid, &arena, 1600);
+        assert_eq!(positions(&selected), vec![2, 0, 1]);
+
+        // without room for the parent the child stays out, even though it would fit
+        let selected = select_greedy(&valid, &arena, 800);
+        assert_eq!(positions(&selected), vec![2]);
+    }
+
+    #[test]
+    fn test_ancestor_feerate_respects_weight() {
+        let (arena, valid) = mempool(&[(1000, 600, &[]), (500, 400, &[])]);
+
+        let selected = select_by_ancestor_feerate(&valid, &arena, 500);
+        assert_eq!(positions(&selected), vec![1]);
+    }
+
+    #[test]
+    fn test_ancestors() {
+        // d spends b and c, which both spend a. e stands alone
+        let parents = vec![vec![], vec![0], vec![0], vec![2, 1], vec![]];
+        assert_eq!(
+            ancestors(&parents),
+            vec![vec![], vec![0], vec![0], vec![0, 1, 2], vec![]]
+        );
+
+        let chain: Vec<Vec<usize>> = (0..1000)
+            .map(|i| if i == 0 { vec![] } else { vec![i - 1] })
+            .collect();
+        assert_eq!(ancestors(&chain)[999], (0..999).collect::<Vec<_>>());
+    }
+
+    // c pays for its package only through a. once a is in on its own, c alone pays less
+    // than b and has to come after it
+    #[test]
+    fn test_ancestor_feerate_rescores_descendants() {
+        let (arena, valid) = mempool(&[(4000, 400, &[]), (1000, 400, &[]), (400, 400, &[0])]);
+
+        let selected = select_by_ancestor_feerate(&valid, &arena, 1200);
+        assert_eq!(positions(&selected), vec![0, 1, 2]);
+    }
+}
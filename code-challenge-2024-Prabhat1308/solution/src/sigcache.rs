@@ -0,0 +1,148 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+
+use crate::{sha256_hash, turn_to_varint, Transaction};
+
+// script rules a verdict was reached under, an entry only counts for the same set
+pub(crate) const VERIFY_P2SH: u32 = 1 << 0;
+pub(crate) const VERIFY_WITNESS: u32 = 1 << 1;
+
+// what check_sig verifies today. taproot spends still pass unchecked, once they are verified
+// a taproot flag joins these and entries written before stop matching
+pub(crate) const STANDARD_FLAGS: u32 = VERIFY_P2SH | VERIFY_WITNESS;
+
+// transactions whose signatures already verified, by wtxid (display order hex), the hash of
+// the outputs they spend and flags. the wtxid commits to the signatures but not to the
+// prevouts the mempool JSON carries, sighashes do commit to their amounts and scripts
+#[derive(Default)]
+pub(crate) struct SigCache {
+    // where the cache is kept between runs, in memory only when unset
+    path: Option<String>,
+    entries: HashSet<(String, String, u32)>,
+    changed: bool,
+    pub(crate) hits: usize,
+}
+
+impl SigCache {
+    // one `wtxid prevouts flags` entry per line, a missing file is an empty cache
+    pub(crate) fn load(path: Option<&str>) -> Result<SigCache, Box<dyn Error>> {
+        let mut cache = SigCache {
+            path: path.map(|p| p.to_string()),
+            ..SigCache::default()
+        };
+        let Some(path) = path else {
+            return Ok(cache);
+        };
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(format!("{}: {}", path, e).into()),
+        };
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(' ').collect();
+            // entries from before they committed to the prevouts would never match
+            if fields.len() == 2 {
+                continue;
+            }
+            let is_hash = |field: &str| field.len() == 64 && hex::decode(field).is_ok();
+            let entry = match fields[..] {
+                [wtxid, prevouts, flags] if is_hash(wtxid) && is_hash(prevouts) => {
+                    u32::from_str_radix(flags, 16)
+                        .ok()
+                        .map(|flags| (wtxid.to_string(), prevouts.to_string(), flags))
+                }
+                _ => None,
+            };
+            let entry = entry
+                .ok_or_else(|| format!("{}:{}: expected wtxid prevouts flags", path, number + 1))?;
+            cache.entries.insert(entry);
+        }
+        Ok(cache)
+    }
+
+    pub(crate) fn contains(&mut self, wtxid: &str, prevouts: &str, flags: u32) -> bool {
+        let found = self
+            .entries
+            .contains(&(wtxid.to_string(), prevouts.to_string(), flags));
+        if found {
+            self.hits += 1;
+        }
+        found
+    }
+
+    pub(crate) fn insert(&mut self, wtxid: String, prevouts: String, flags: u32) {
+        self.changed |= self.entries.insert((wtxid, prevouts, flags));
+    }
+
+    // writes the cache back when it has a file and learned something new, sorted so the
+    // file doesn't churn between runs
+    pub(crate) fn save(&self) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.changed {
+            return Ok(());
+        }
+
+        let mut lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(wtxid, prevouts, flags)| format!("{} {} {:08x}", wtxid, prevouts, flags))
+            .collect();
+        lines.sort();
+        lines.push(String::new());
+        fs::write(path, lines.join("\n")).map_err(|e| format!("{}: {}", path, e).into())
+    }
+}
+
+// sha256 of the value and scriptPubKey of every output the inputs spend, in input order
+pub(crate) fn prevouts_hash(tx: &Transaction) -> String {
+    let mut preimage = Vec::new();
+    for ins in &tx.vin {
+        let script = hex::decode(&ins.prevout.scriptpubkey).unwrap_or_default();
+        preimage.extend_from_slice(&ins.prevout.value.to_le_bytes());
+        preimage.extend_from_slice(&turn_to_varint(script.len() as u64));
+        preimage.extend_from_slice(&script);
+    }
+    hex::encode(sha256_hash(&preimage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sig_cache_roundtrip() {
+        let path = std::env::temp_dir().join(format!("sigcache-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let wtxid = "ab".repeat(32);
+        let prevouts = "cd".repeat(32);
+        let mut cache = SigCache::load(Some(path)).unwrap();
+        assert!(!cache.contains(&wtxid, &prevouts, STANDARD_FLAGS));
+        cache.insert(wtxid.clone(), prevouts.clone(), STANDARD_FLAGS);
+        cache.save().unwrap();
+
+        let mut cache = SigCache::load(Some(path)).unwrap();
+        assert!(cache.contains(&wtxid, &prevouts, STANDARD_FLAGS));
+        assert!(!cache.contains(&wtxid, &prevouts, VERIFY_P2SH));
+        assert!(!cache.contains(&wtxid, &"ef".repeat(32), STANDARD_FLAGS));
+        assert_eq!(cache.hits, 1);
+
+        // a cache from before prevouts were part of the key loads empty
+        fs::write(path, format!("{} {:08x}\n", wtxid, STANDARD_FLAGS)).unwrap();
+        assert!(SigCache::load(Some(path)).unwrap().entries.is_empty());
+
+        fs::write(path, "not a cache line\n").unwrap();
+        assert!(SigCache::load(Some(path)).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
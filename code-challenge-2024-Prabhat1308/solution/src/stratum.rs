@@ -0,0 +1,279 @@
+use num_bigint::BigUint;
+use serde_json::{json, Value};
+
+use crate::merkle::{merkle_branch, txid_to_bytes};
+use crate::{sha256_hash, target_from_bits, BlockTemplate, BLOCK_BITS, BLOCK_VERSION};
+use crate::{EXTRANONCE1, EXTRANONCE2_SIZE};
+
+// a Stratum v1 job: what mining.subscribe and mining.notify hand to a miner
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StratumJob {
+    pub job_id: String,
+    // internal byte order, as it sits in the header
+    pub prev_hash: [u8; 32],
+    pub coinb1: Vec<u8>,
+    pub coinb2: Vec<u8>,
+    // siblings of the coinbase from the bottom of the tree up, internal byte order
+    pub merkle_branch: Vec<[u8; 32]>,
+    pub version: u32,
+    pub bits: u32,
+    pub time: u32,
+    pub extranonce1: Vec<u8>,
+    pub extranonce2_size: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShareCheck {
+    pub header: Vec<u8>,
+    // displayed byte order
+    pub hash: [u8; 32],
+    pub meets_share_target: bool,
+    pub meets_block_target: bool,
+}
+
+// stratum sends the previous block hash with every 4 byte word reversed
+fn swap_words(bytes: &[u8; 32]) -> [u8; 32] {
+    let mut swapped = *bytes;
+    for word in swapped.chunks_mut(4) {
+        word.reverse();
+    }
+    swapped
+}
+
+fn hex_u32(value: &Value, name: &str) -> Result<u32, String> {
+    let hex_str = value.as_str().ok_or(format!("{} is not a string", name))?;
+    u32::from_str_radix(hex_str, 16).map_err(|_| format!("{} is not hex: {}", name, hex_str))
+}
+
+fn hex_bytes(value: &Value, name: &str) -> Result<Vec<u8>, String> {
+    let hex_str = value.as_str().ok_or(format!("{} is not a string", name))?;
+    hex::decode(hex_str).map_err(|_| format!("{} is not hex: {}", name, hex_str))
+}
+
+fn hash32(bytes: Vec<u8>, name: &str) -> Result<[u8; 32], String> {
+    bytes
+        .try_into()
+        .map_err(|_| format!("{} is not 32 bytes", name))
+}
+
+impl StratumJob {
+    // a job for the template on top of the all zero previous block `mine` builds on
+    pub fn from_template(template: &BlockTemplate, time: u32) -> Result<StratumJob, String> {
+        // the coinbase leaf only holds a place, the miner hashes its own coinbase into it
+        let mut leaves = vec![[0u8; 32]];
+        for txid in &template.txids {
+            leaves.push(txid_to_bytes(txid)?);
+        }
+
+        Ok(StratumJob {
+            job_id: format!("{:x}", template.height),
+            prev_hash: [0u8; 32],
+            coinb1: template.coinbase_parts.coinb1.clone(),
+            coinb2: template.coinbase_parts.coinb2.clone(),
+            merkle_branch: merkle_branch(&leaves, 0).unwrap().hashes,
+            version: BLOCK_VERSION,
+            bits: BLOCK_BITS,
+            time,
+            extranonce1: EXTRANONCE1.to_vec(),
+            extranonce2_size: EXTRANONCE2_SIZE,
+        })
+    }
+
+    // the mining.subscribe result and the mining.notify message, one JSON line each
+    pub fn to_messages(&self) -> Vec<String> {
+        let subscribe = json!({
+            "id": 1,
+            "result": [
+                [["mining.notify", self.job_id]],
+                hex::encode(&self.extranonce1),
+                self.extranonce2_size,
+            ],
+            "error": null,
+        });
+        let notify = json!({
+            "id": null,
+            "method": "mining.notify",
+            "params": [
+                self.job_id,
+                hex::encode(swap_words(&self.prev_hash)),
+                hex::encode(&self.coinb1),
+                hex::encode(&self.coinb2),
+                self.merkle_branch.iter().map(hex::encode).collect::<Vec<_>>(),
+                format!("{:08x}", self.version),
+                format!("{:08x}", self.bits),
+                format!("{:08x}", self.time),
+                true,
+            ],
+        });
+        vec![subscribe.to_string(), notify.to_string()]
+    }
+
+    // reads back what to_messages writes
+    pub fn from_messages(text: &str) -> Result<StratumJob, String> {
+        let mut subscribe = None;
+        let mut notify = None;
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let message: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+            if message["method"] == "mining.notify" {
+                notify = Some(message);
+            } else if message["result"].is_array() {
+                subscribe = Some(message);
+            }
+        }
+        let subscribe = subscribe.ok_or("no mining.subscribe result")?;
+        let notify = notify.ok_or("no mining.notify message")?;
+
+        let result = &subscribe["result"];
+        let params = notify["params"]
+            .as_array()
+            .ok_or("mining.notify without params")?;
+        if params.len() < 9 {
+            return Err("mining.notify needs 9 params".to_string());
+        }
+
+        let merkle_branch = params[4]
+            .as_array()
+            .ok_or("merkle branch is not a list")?
+            .iter()
+            .map(|hash| hash32(hex_bytes(hash, "merkle branch")?, "merkle branch"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(StratumJob {
+            job_id: params[0]
+                .as_str()
+                .ok_or("job id is not a string")?
+                .to_string(),
+            prev_hash: swap_words(&hash32(hex_bytes(&params[1], "prevhash")?, "prevhash")?),
+            coinb1: hex_bytes(&params[2], "coinb1")?,
+            coinb2: hex_bytes(&params[3], "coinb2")?,
+            merkle_branch,
+            version: hex_u32(&params[5], "version")?,
+            bits: hex_u32(&params[6], "nbits")?,
+            time: hex_u32(&params[7], "ntime")?,
+            extranonce1: hex_bytes(&result[1], "extranonce1")?,
+            extranonce2_size: result[2]
+                .as_u64()
+                .ok_or("extranonce2_size is not a number")? as usize,
+        })
+    }
+
+    // the 80 byte header a miner built for this job
+    pub fn header(&self, extranonce2: &[u8], time: u32, nonce: u32) -> Result<Vec<u8>, String> {
+        if extranonce2.len() != self.extranonce2_size {
+            return Err(format!(
+                "extranonce2 is {} bytes, the job wants {}",
+                extranonce2.len(),
+                self.extranonce2_size
+            ));
+        }
+
+        let mut coinbase = self.coinb1.clone();
+        coinbase.extend_from_slice(&self.extranonce1);
+        coinbase.extend_from_slice(extranonce2);
+        coinbase.extend_from_slice(&self.coinb2);
+
+        // the coinbase is always the leftmost leaf
+        let mut root = sha256_hash(&sha256_hash(&coinbase));
+        for sibling in &self.merkle_branch {
+            root.extend_from_slice(sibling);
+            root = sha256_hash(&sha256_hash(&root));
+        }
+
+        let mut header = Vec::with_capacity(80);
+        header.extend_from_slice(&self.version.to_le_bytes());
+        header.extend_from_slice(&self.prev_hash);
+        header.extend_from_slice(&root);
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&self.bits.to_le_bytes());
+        header.extend_from_slice(&nonce.to_le_bytes());
+        Ok(header)
+    }
+
+    // a share as mining.submit sends it: extranonce2, ntime and nonce
+    pub fn check_share(
+        &self,
+        extranonce2: &[u8],
+        time: u32,
+        nonce: u32,
+        share_target: &BigUint,
+    ) -> Result<ShareCheck, String> {
+        let header = self.header(extranonce2, time, nonce)?;
+        let mut hash: [u8; 32] = sha256_hash(&sha256_hash(&header)).try_into().unwrap();
+        hash.reverse();
+
+        let value = BigUint::from_bytes_be(&hash);
+        Ok(ShareCheck {
+            header,
+            hash,
+            meets_share_target: value <= *share_target,
+            meets_block_target: value <= target_from_bits(self.bits),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_coinbase_transaction, merkle, raw_txid};
+
+    fn job() -> (StratumJob, Vec<[u8; 32]>) {
+        let parts =
+            get_coinbase_transaction(840000, 1000, 5000000000, Some(vec![0u8; 32]), [0u8; 32]);
+        let mut leaves = vec![[0u8; 32]];
+        leaves.extend((1..6u8).map(|i| [i; 32]));
+
+        let job = StratumJob {
+            job_id: "1".to_string(),
+            prev_hash: [9u8; 32],
+            coinb1: parts.coinb1.clone(),
+            coinb2: parts.coinb2.clone(),
+            merkle_branch: merkle_branch(&leaves, 0).unwrap().hashes,
+            version: 4,
+            bits: 0x1f00ffff,
+            time: 1700000000,
+            extranonce1: EXTRANONCE1.to_vec(),
+            extranonce2_size: EXTRANONCE2_SIZE,
+        };
+        (job, leaves)
+    }
+
+    #[test]
+    fn test_stratum_messages_roundtrip() {
+        let (job, _) = job();
+        let messages = job.to_messages();
+        assert_eq!(
+            StratumJob::from_messages(&messages.join("\n")).unwrap(),
+            job
+        );
+    }
+
+    #[test]
+    fn test_share_header_matches_block() {
+        let (job, mut leaves) = job();
+        let extranonce2 = [1, 2, 3, 4];
+
+        let mut extranonce = EXTRANONCE1.to_vec();
+        extranonce.extend_from_slice(&extranonce2);
+        let coinbase =
+            get_coinbase_transaction(840000, 1000, 5000000000, Some(vec![0u8; 32]), [0u8; 32])
+                .assemble(&extranonce);
+        leaves[0] =
+            merkle::txid_to_bytes(&raw_txid(&hex::decode(coinbase).unwrap()).unwrap()).unwrap();
+
+        let header = job.header(&extranonce2, 1700000001, 7).unwrap();
+        assert_eq!(header[36..68], merkle::merkle_root(&leaves));
+        assert_eq!(header[4..36], [9u8; 32]);
+        assert!(job.header(&[0; 3], 0, 0).is_err());
+
+        let check = job
+            .check_share(&extranonce2, 1700000001, 7, &BigUint::from(0u8))
+            .unwrap();
+        assert!(!check.meets_share_target);
+        let easiest = BigUint::from_bytes_be(&[0xff; 32]);
+        assert!(
+            job.check_share(&extranonce2, 0, 0, &easiest)
+                .unwrap()
+                .meets_share_target
+        );
+    }
+}